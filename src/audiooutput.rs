@@ -0,0 +1,115 @@
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    DefaultStreamConfigError, FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+/// Source of the samples of an audio output stream
+pub trait AudioRenderer: Send + 'static {
+    /// Fill `data`, which contains interleaved frames, with the next samples
+    fn render<T: Sample + FromSample<f32>>(&mut self, data: &mut [T]);
+}
+
+/// Output device and its default stream configuration
+///
+/// `device_name` selects the first device whose name contains it, ignoring case, otherwise the
+/// default output device is used.
+pub fn output_device(
+    device_name: Option<&str>,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig), String> {
+    let audio_host = cpal::default_host();
+    let device = match device_name {
+        Some(device_name) => {
+            let devices = match audio_host.output_devices() {
+                Ok(devices) => devices.collect::<Vec<_>>(),
+                Err(err) => return Err(format!("Could not list audio devices: {:?}", err)),
+            };
+            let names: Vec<String> = devices
+                .iter()
+                .map(|device| device.name().unwrap_or_default())
+                .collect();
+            let wanted = device_name.to_lowercase();
+            match names
+                .iter()
+                .position(|name| name.to_lowercase().contains(&wanted))
+            {
+                Some(index) => devices.into_iter().nth(index).unwrap(),
+                None => {
+                    return Err(format!(
+                        "No audio device matches \"{}\", available are: {}",
+                        device_name,
+                        names.join(", ")
+                    ))
+                }
+            }
+        }
+        None => match audio_host.default_output_device() {
+            Some(x) => x,
+            None => return Err(format!("No audio device for {:?}", audio_host.id())),
+        },
+    };
+    let default_config = {
+        match device.default_output_config() {
+            Ok(x) => x,
+            Err(y) => return Err(format!("No output configuration on output device: {:?}", y)),
+        }
+    };
+    Ok((device, default_config))
+}
+
+/// Whether the default host has a default output device that is available
+pub fn has_default_output_device() -> bool {
+    match cpal::default_host().default_output_device() {
+        Some(device) => !matches!(
+            device.default_output_config(),
+            Err(DefaultStreamConfigError::DeviceNotAvailable)
+        ),
+        None => false,
+    }
+}
+
+/// Names of the audio output devices
+pub fn output_device_names() -> Result<Vec<String>, String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => Ok(devices.map(|x| x.name().unwrap_or_default()).collect()),
+        Err(err) => Err(format!("Could not list audio devices: {:?}", err)),
+    }
+}
+
+/// Create an output stream that is fed by `renderer`
+pub fn create_cpal_stream<R: AudioRenderer>(
+    device: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    renderer: R,
+) -> Result<Stream, String> {
+    let sampletype = config.sample_format();
+    let my_config = config.into();
+
+    let stream = match sampletype {
+        SampleFormat::F32 => build_output_stream::<f32, R>(&device, &my_config, renderer),
+        SampleFormat::I16 => build_output_stream::<i16, R>(&device, &my_config, renderer),
+        SampleFormat::U16 => build_output_stream::<u16, R>(&device, &my_config, renderer),
+        format => return Err(format!("Unsupported sample format {}", format)),
+    };
+
+    match stream {
+        Ok(stream) => Ok(stream),
+        Err(x) => Err(format!(
+            "Streamconfig {:?} is not supported, got error: {:?}",
+            my_config, x
+        )),
+    }
+}
+
+fn build_output_stream<T: SizedSample + FromSample<f32>, R: AudioRenderer>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut renderer: R,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| renderer.render(data),
+        err_fn,
+        None,
+    )
+}
@@ -35,13 +35,6 @@ pub struct AudioSignal<T> {
     pub tone: ToneConfiguration,
 }
 
-impl<T: Copy> AudioSignal<T> {
-    pub fn get_next_sample(&mut self) -> T {
-        self.index = (self.index + 1) % self.signal.len();
-        self.signal[self.index]
-    }
-}
-
 impl From<AudioSignal<f32>> for AudioSignal<u16> {
     fn from(audio_signal: AudioSignal<f32>) -> Self {
         let mut audio: Vec<u16> = Vec::with_capacity(audio_signal.signal.len());
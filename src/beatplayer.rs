@@ -1,18 +1,37 @@
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    SampleFormat, Stream,
-};
+use cpal::{traits::StreamTrait, Stream};
 
 use crate::{
-    audiosignal::{samples_to_time, AudioSignal, ToneConfiguration},
+    audiooutput::{create_cpal_stream, has_default_output_device, output_device},
+    audiosignal::{
+        frequency_relative_semitone_equal_temperament, samples_to_time, time_in_samples,
+        AudioSignal, ToneConfiguration,
+    },
+    beatrenderer::BeatRenderer,
+    drone::Drone,
+    midiclock::{ClockSender, PulseGrid, SharedTransport},
+    netsync::{SharedTimeline, Timeline},
+    pitch::{note_name, DEFAULT_A4},
+    playbackclock::{
+        format_elapsed, samples_per_beat, BeatGrid, MusicalPosition, PlaybackClock, TICKS_PER_BEAT,
+    },
     repl::repl::ReplApp,
+    sessiontimer::{SessionTimer, TimerLimit},
+    settingsfile::Entry,
+    softwareclock::{SoftwareClock, TerminalBell, SOFTWARE_SAMPLE_RATE},
+    statusline::StatusTemplate,
+    tempomap::TempoMap,
+    tuning::{parse_root, Tuning},
 };
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     f64,
     fmt::Display,
+    iter::{Enumerate, Peekable},
+    path::Path,
+    str::Chars,
     sync::Mutex,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use crossterm::style::Attribute;
@@ -58,25 +77,68 @@ impl Display for BeatPatternType {
     }
 }
 
+/// Upper limit for the number of beats of a pattern, guards against huge repeat counts
+const MAX_PATTERN_BEATS: usize = 4096;
+
+/// More beats since the last poll are a jump of the clock, of which only the current beat is
+/// reported
+const MAX_REPORTED_BEATS: u64 = 16;
+
 /// Metronome beat pattern
+///
+/// The pattern is a cycle of one or more bars that may differ in length.
 #[derive(Debug, Clone)]
 pub struct BeatPattern {
     pub pattern: Vec<BeatPatternType>,
+    /// Number of beats of each bar, they add up to the length of `pattern`
+    pub bars: Vec<usize>,
     pub index: Option<usize>,
 }
 
 impl BeatPattern {
     pub fn new(pattern: Vec<BeatPatternType>) -> BeatPattern {
+        let bars = if pattern.is_empty() {
+            Vec::new()
+        } else {
+            vec![pattern.len()]
+        };
         BeatPattern {
             pattern,
+            bars,
             index: None,
         }
     }
 
+    /// Bar and beat within that bar of a beat, both counted from the start of the cycle
+    pub fn bar_and_beat(&self, beat: u64) -> (u64, u64) {
+        let mut bar = beat / self.pattern.len() as u64 * self.bars.len() as u64;
+        let mut beat = beat % self.pattern.len() as u64;
+        for &length in &self.bars {
+            if beat < length as u64 {
+                break;
+            }
+            beat -= length as u64;
+            bar += 1;
+        }
+        (bar, beat)
+    }
+
+    /// Number of beats from the start of the cycle to the start of a bar
+    pub fn bar_onset(&self, bar: u64) -> u64 {
+        let cycles = bar / self.bars.len() as u64;
+        let bars = (bar % self.bars.len() as u64) as usize;
+        cycles * self.pattern.len() as u64 + self.bars[..bars].iter().sum::<usize>() as u64
+    }
+
     /// String with the current beat marked
     pub fn to_string_with_current_beat(&self) -> String {
         let mut res = String::new();
+        let mut bar_ends = self.bar_ends();
         for (idx, beat) in self.pattern.iter().enumerate() {
+            if bar_ends.peek() == Some(&idx) {
+                bar_ends.next();
+                res.push('|');
+            }
             if Some(idx) == self.index {
                 res.extend(
                     format!(
@@ -93,18 +155,115 @@ impl BeatPattern {
         }
         res
     }
+
+    /// Indices of the beats that start the second and further bars
+    fn bar_ends(&self) -> std::iter::Peekable<impl Iterator<Item = usize> + '_> {
+        self.bars
+            .iter()
+            .scan(0, |end, length| {
+                *end += length;
+                Some(*end)
+            })
+            .take(self.bars.len().saturating_sub(1))
+            .peekable()
+    }
+}
+
+/// Characters of a pattern with their index
+type PatternChars<'a> = Peekable<Enumerate<Chars<'a>>>;
+
+/// Parse beats and bar lines, which are `None`, up to the end or the end of a group
+fn parse_pattern_elements(
+    chars: &mut PatternChars,
+    group_column: Option<usize>,
+) -> Result<Vec<Option<BeatPatternType>>, String> {
+    let mut elements = Vec::new();
+    while let Some((index, element)) = chars.next() {
+        let column = index + 1;
+        match element {
+            '|' => elements.push(None),
+            '(' => {
+                let group = parse_pattern_elements(chars, Some(column))?;
+                let repeats = parse_repeats(chars)?;
+                let length = group
+                    .len()
+                    .checked_mul(repeats)
+                    .and_then(|x| x.checked_add(elements.len()));
+                if length.is_none_or(|x| x > MAX_PATTERN_BEATS) {
+                    return Err(format!("Column {}: pattern is too long", column));
+                }
+                for _ in 0..repeats {
+                    elements.extend_from_slice(&group);
+                }
+            }
+            ')' if group_column.is_some() => return Ok(elements),
+            ')' => return Err(format!("Column {}: \")\" without \"(\"", column)),
+            // comment until the end of the line
+            '#' => while chars.next_if(|&(_, x)| x != '\n').is_some() {},
+            x if x.is_whitespace() => (),
+            x => match BeatPatternType::try_from(&x) {
+                Ok(beat) => elements.push(Some(beat)),
+                Err(_) => {
+                    return Err(format!(
+                        "Column {}: unexpected character \"{}\", expected one of `!+.|()#`",
+                        column, x
+                    ))
+                }
+            },
+        }
+    }
+    match group_column {
+        Some(column) => Err(format!("Column {}: \"(\" is never closed", column)),
+        None => Ok(elements),
+    }
+}
+
+/// Parse the repeat count like `x3` after a group, a group without it is played once
+fn parse_repeats(chars: &mut PatternChars) -> Result<usize, String> {
+    let column = match chars.next_if(|&(_, x)| x == 'x') {
+        Some((index, _)) => index + 2,
+        None => return Ok(1),
+    };
+    let mut count = String::new();
+    while let Some((_, digit)) = chars.next_if(|(_, x)| x.is_ascii_digit()) {
+        count.push(digit);
+    }
+    match count.parse::<usize>() {
+        Ok(count) if count > MAX_PATTERN_BEATS => {
+            Err(format!("Column {}: repeat count is too large", column))
+        }
+        Ok(count) if count > 0 => Ok(count),
+        // too many digits for a usize are too large as well
+        Err(_) if !count.is_empty() => Err(format!("Column {}: repeat count is too large", column)),
+        _ => Err(format!("Column {}: expected a repeat count > 0", column)),
+    }
 }
 
 impl TryFrom<&str> for BeatPattern {
     type Error = String;
 
+    /// Parse a pattern like `!+++`, `!+++|!++` or `(!++)x3 !+++  # comment`
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let elements = parse_pattern_elements(&mut value.chars().enumerate().peekable(), None)?;
         let mut result = BeatPattern {
-            pattern: Vec::with_capacity(value.len()),
+            pattern: Vec::with_capacity(elements.len()),
+            bars: Vec::new(),
             index: None,
         };
-        for element in value.chars() {
-            result.pattern.push(BeatPatternType::try_from(&element)?);
+        let mut bar_length = 0;
+        for element in elements {
+            match element {
+                Some(beat) => {
+                    result.pattern.push(beat);
+                    bar_length += 1;
+                }
+                // empty bars are skipped
+                None if bar_length == 0 => (),
+                None => result.bars.push(std::mem::take(&mut bar_length)),
+            }
+        }
+        if bar_length > 0 {
+            result.bars.push(bar_length);
         }
         Ok(result)
     }
@@ -113,56 +272,219 @@ impl TryFrom<&str> for BeatPattern {
 impl Display for BeatPattern {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut res = String::new();
-        for beat in &self.pattern {
+        let mut bar_ends = self.bar_ends();
+        for (idx, beat) in self.pattern.iter().enumerate() {
+            if bar_ends.peek() == Some(&idx) {
+                bar_ends.next();
+                res.push('|');
+            }
             res.push(beat.into());
         }
         write!(f, "{}", res)
     }
 }
 
+/// Beat that the audio clock is playing, e.g. for visual cues
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayedBeat {
+    pub position: MusicalPosition,
+    /// Number of beats of the current bar
+    pub beats_in_bar: u64,
+    pub beat_type: BeatPatternType,
+    pub since_onset: Duration,
+    pub length: Duration,
+    /// Tempo of the beat, which is the one of the current section of a tempo map
+    pub bpm: f64,
+}
+
+/// What advances the clock of a playback run
+enum PlaybackOutput {
+    Audio(Stream),
+    /// Software clock of the silent mode
+    Silent {
+        _clock: SoftwareClock,
+    },
+}
+
 pub struct StreamWrapper {
-    stream: Stream,
-    start_time: Instant,
+    output: PlaybackOutput,
+    clock: PlaybackClock,
+    sample_rate: f64,
+    grid: BeatGrid,
+    /// Bar of the pattern cycle at which the bar counting of this playback run starts
+    bar_origin: u64,
+    /// Frame at which the time measurement of this playback run starts
+    frame_origin: u64,
+    /// MIDI clock of this playback run, it sends stop when it is dropped with the stream
+    _clock_sender: Option<ClockSender>,
+    /// Terminal bell of this playback run
+    _bell: Option<TerminalBell>,
+}
+
+/// Bars and playing time accumulated by previous playback runs
+#[derive(Debug, Clone, Default)]
+struct PlaybackCounter {
+    bars: u64,
+    elapsed: Duration,
 }
 
 /// A metronome sound player that realizes the beat playback
 // #[derive(Debug)]
 pub struct BeatPlayer {
-    pub bpm: u16,
+    pub bpm: f64,
     pub beat_value: u16,
     pub beat: ToneConfiguration,
     pub ac_beat: ToneConfiguration,
     pub beat_pattern: BeatPattern,
+    /// Reference pitch of A4 for note names
+    pub a4: f64,
+    /// Tuning that note names, relative pitches and drone intervals resolve through
+    pub tuning: Tuning,
+    /// Show the note names of the pitches in the status line
+    pub show_note_names: bool,
+    /// Reference tone that plays independently of the beat playback
+    pub drone: Drone,
+    /// Name or part of the name of the audio output device, `None` for the default device
+    pub device: Option<String>,
+    /// Play without audio, the software clock drives the visual cues and the events
+    pub silent: bool,
+    /// Tempo map that the playback follows instead of bpm, value and pattern, with the pattern of
+    /// all its bars
+    tempo_map: Option<(TempoMap, BeatPattern)>,
+    /// Ring the terminal bell at every beat
+    bell: bool,
+    /// Transport to which the playback sends MIDI clock
+    midi_clock: Option<SharedTransport>,
+    /// Timeline of the playback runs that is synchronized over the network
+    network_sync: Option<SharedTimeline>,
     stream: Option<StreamWrapper>,
     start_stop_mtx: Mutex<()>,
+    counter: PlaybackCounter,
+    /// Practice session timer, its limit is absolute with regard to the counters
+    timer: Option<SessionTimer>,
+    /// Last beat that `poll_events` reported
+    last_beat: Option<u64>,
+    /// Playback state and settings that `poll_events` reported
+    reported_state: Option<(bool, String)>,
+    /// User-defined status line, `None` for the default one
+    pub status_template: Option<StatusTemplate>,
 }
 
 impl ReplApp for BeatPlayer {
     fn get_status(&mut self) -> String {
-        self.update_pattern_counter();
-        format!(
-            "pattern: {}  value: 1/{} bpm: {}  !: {:.3}Hz  +:{:.3}Hz",
-            &self.beat_pattern.to_string_with_current_beat(),
-            &self.beat_value,
-            &self.bpm,
-            &self.ac_beat.frequency,
-            &self.beat.frequency
-        )
+        let values = self.status_values();
+        if let Some(template) = &self.status_template {
+            return template.render(&values);
+        }
+        let (ac_note, note) = if self.show_note_names {
+            (
+                format!(" ({})", values["accent_note"]),
+                format!(" ({})", values["note"]),
+            )
+        } else {
+            (String::new(), String::new())
+        };
+        let mut status = format!(
+            "pattern: {}  value: 1/{} bpm: {}  !: {}Hz{}  +:{}Hz{}  bar: {}  pos: {}  time: {}",
+            values["pattern"],
+            values["value"],
+            values["bpm"],
+            values["accent_hz"],
+            ac_note,
+            values["beat_hz"],
+            note,
+            values["bar"],
+            values["pos"],
+            values["elapsed"],
+        );
+        if !values["map"].is_empty() {
+            status += format!("  map: {}", values["map"]).as_ref();
+        }
+        if let Some(StreamWrapper {
+            output: PlaybackOutput::Silent { .. },
+            ..
+        }) = &self.stream
+        {
+            status += match self.silent {
+                true => "  silent",
+                false => "  silent: no audio device",
+            };
+        }
+        if self.tuning != Tuning::default() {
+            status += format!("  tuning: {}", values["tuning"]).as_ref();
+        }
+        if !values["timer"].is_empty() {
+            status += format!("  timer: {}", values["timer"]).as_ref();
+        }
+        if !values["drone"].is_empty() {
+            status += format!("  drone: {}", values["drone"]).as_ref();
+        }
+        status
     }
 
     fn get_event_interval(&self) -> Duration {
-        let events_per_sec = self.bpm as f64 / 60.0;
+        let bpm = match &self.tempo_map {
+            Some((map, _)) => map.sections.iter().map(|x| x.bpm).fold(self.bpm, f64::max),
+            None => self.bpm,
+        };
+        let events_per_sec = bpm / 60.0;
         std::time::Duration::from_secs_f64(1.0 / events_per_sec)
     }
+
+    /// Changes of the playback state and the settings, e.g. `state playing` or
+    /// `settings bpm=100 value=4 ...`, and one line per played beat with its time, its position and
+    /// its type, e.g. `beat 2.400 2:1 !`
+    fn poll_events(&mut self) -> Vec<String> {
+        self.update_timer();
+        let mut events = Vec::new();
+        let playing = self.is_playing();
+        let settings: Vec<String> = self
+            .settings()
+            .iter()
+            .map(|entry| match entry.value.contains(char::is_whitespace) {
+                true => format!("{}=\"{}\"", entry.key, entry.value),
+                false => format!("{}={}", entry.key, entry.value),
+            })
+            .collect();
+        let settings = settings.join(" ");
+        let (was_playing, reported_settings) = match self
+            .reported_state
+            .replace((playing, settings.clone()))
+        {
+            Some((was_playing, reported_settings)) => (Some(was_playing), Some(reported_settings)),
+            None => (None, None),
+        };
+        if was_playing != Some(playing) {
+            let state = if playing { "playing" } else { "stopped" };
+            events.push(format!("state {}", state));
+        }
+        if reported_settings != Some(settings) {
+            events.push(format!(
+                "settings {}",
+                self.reported_state.as_ref().unwrap().1
+            ));
+        }
+        events.extend(self.beat_events());
+        events
+    }
+
+    fn is_active(&mut self) -> bool {
+        self.update_timer();
+        self.is_playing()
+    }
+
+    fn bar_count(&mut self) -> Option<u64> {
+        Some(self.completed_bars())
+    }
 }
 
 impl Display for BeatPlayer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "bpm: {:4}, beat_value: 1/{}, pattern: {:?}, accent: {:.2}Hz, normal: {:.2}Hz, \
+            "bpm: {:>4}, beat_value: 1/{}, pattern: {:?}, accent: {:.2}Hz, normal: {:.2}Hz, \
             playing: {}",
-            self.bpm,
+            format_bpm(self.bpm),
             self.beat_value,
             self.beat_pattern,
             self.ac_beat.frequency,
@@ -174,7 +496,7 @@ impl Display for BeatPlayer {
 
 impl BeatPlayer {
     pub fn new(
-        bpm: u16,
+        bpm: f64,
         beat_value: u16,
         beat: ToneConfiguration,
         ac_beat: ToneConfiguration,
@@ -186,8 +508,23 @@ impl BeatPlayer {
             beat,
             ac_beat,
             beat_pattern,
+            a4: DEFAULT_A4,
+            tuning: Tuning::default(),
+            show_note_names: false,
+            drone: Drone::new(),
+            device: None,
+            silent: false,
+            bell: false,
+            tempo_map: None,
+            midi_clock: None,
+            network_sync: None,
             stream: None,
             start_stop_mtx: Mutex::new(()),
+            counter: PlaybackCounter::default(),
+            timer: None,
+            last_beat: None,
+            reported_state: None,
+            status_template: None,
         }
     }
 
@@ -203,11 +540,109 @@ impl BeatPlayer {
             .start_stop_mtx
             .lock()
             .expect("Playback start mutex is poisoned, aborting");
-        if let Some(x) = self.stream.as_mut() {
-            x.stream.pause().expect("Error during pause");
+        if let Some(StreamWrapper {
+            output: PlaybackOutput::Audio(stream),
+            ..
+        }) = self.stream.as_mut()
+        {
+            stream.pause().expect("Error during pause");
+        };
+        // keep the counters of this playback run
+        self.counter = PlaybackCounter {
+            bars: self.completed_bars(),
+            elapsed: self.elapsed(),
         };
         self.stream = None;
         self.beat_pattern.index = None;
+        if let Some((_, pattern)) = &mut self.tempo_map {
+            pattern.index = None;
+        }
+        self.publish_timeline();
+    }
+
+    /// Pattern that the playback follows, the one of the tempo map if there is one
+    fn playback_pattern(&self) -> &BeatPattern {
+        match &self.tempo_map {
+            Some((_, pattern)) => pattern,
+            None => &self.beat_pattern,
+        }
+    }
+
+    fn playback_pattern_mut(&mut self) -> &mut BeatPattern {
+        match &mut self.tempo_map {
+            Some((_, pattern)) => pattern,
+            None => &mut self.beat_pattern,
+        }
+    }
+
+    /// Send MIDI clock, start, stop and song position to `transport` while playing
+    ///
+    /// Stops and resumes playback if playback is running
+    pub fn set_midi_clock(&mut self, transport: Option<SharedTransport>) -> Result<(), String> {
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+        self.midi_clock = transport;
+        if restart {
+            self.play_beat()?;
+        }
+        Ok(())
+    }
+
+    /// Publish the playback runs to `timeline`, which the network sync and a followed MIDI clock
+    /// align with the other instances or devices
+    pub fn set_network_sync(&mut self, timeline: Option<SharedTimeline>) {
+        self.network_sync = timeline;
+        self.publish_timeline();
+    }
+
+    fn publish_timeline(&self) {
+        let shared = match &self.network_sync {
+            Some(shared) => shared,
+            None => return,
+        };
+        let settings = self
+            .settings()
+            .into_iter()
+            .filter(|entry| matches!(entry.key.as_str(), "bpm" | "value" | "pattern"))
+            .collect();
+        *shared.lock().unwrap() = self.stream.as_ref().map(|stream| Timeline {
+            clock: stream.clock.clone(),
+            sample_rate: stream.sample_rate,
+            settings,
+        });
+    }
+
+    /// Tempo map that the playback follows instead of bpm, value and pattern
+    pub fn tempo_map(&self) -> Option<&TempoMap> {
+        self.tempo_map.as_ref().map(|(map, _)| map)
+    }
+
+    /// Follow a tempo map or, with `None`, bpm, value and pattern again
+    ///
+    /// Setting bpm, value or pattern also ends following the tempo map. Stops and resumes
+    /// playback if playback is running.
+    pub fn set_tempo_map(&mut self, tempo_map: Option<TempoMap>) -> Result<(), String> {
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+        let previous = self.tempo_map.take();
+        self.tempo_map = tempo_map.map(|map| {
+            let pattern = map.pattern();
+            (map, pattern)
+        });
+        if restart && self.play_beat().is_err() {
+            self.tempo_map = previous;
+            Err("Tempo map does not seem to work, returning to previous rhythm".to_string())
+        } else {
+            Ok(())
+        }
     }
 
     /// Set the beat pattern
@@ -224,11 +659,16 @@ impl BeatPlayer {
             false
         };
 
-        let previous_pattern = beat_pattern.pattern.clone();
-        self.beat_pattern.pattern.clone_from(&beat_pattern.pattern);
+        let previous_pattern = self.beat_pattern.clone();
+        let previous_map = self.tempo_map.take();
+        self.beat_pattern = BeatPattern {
+            index: None,
+            ..beat_pattern.clone()
+        };
 
         if restart && self.play_beat().is_err() {
-            self.beat_pattern.pattern = previous_pattern;
+            self.beat_pattern = previous_pattern;
+            self.tempo_map = previous_map;
             Err("New pattern does not seem to work, returning to previous pattern".to_string())
         } else {
             Ok(())
@@ -254,21 +694,23 @@ impl BeatPlayer {
         };
 
         let previous_beat_value = self.beat_value;
+        let previous_map = self.tempo_map.take();
         self.beat_value = beat_value;
 
         if restart && self.play_beat().is_err() {
             self.beat_value = previous_beat_value;
+            self.tempo_map = previous_map;
             false
         } else {
             true
         }
     }
 
-    /// Set the beats per minute
+    /// Set the beats per minute, fractional values are allowed
     ///
     /// Stops and resumes playback if playback is running
-    pub fn set_bpm(&mut self, bpm: u16) -> bool {
-        if bpm == 0 {
+    pub fn set_bpm(&mut self, bpm: f64) -> bool {
+        if !bpm.is_finite() || bpm <= 0.0 {
             return false;
         }
 
@@ -280,16 +722,58 @@ impl BeatPlayer {
         };
 
         let previous_bpm = self.bpm;
+        let previous_map = self.tempo_map.take();
         self.bpm = bpm;
 
         if restart && self.play_beat().is_err() {
             self.bpm = previous_bpm;
+            self.tempo_map = previous_map;
             false
         } else {
             true
         }
     }
 
+    /// Set pattern, beat value and bpm at once
+    ///
+    /// Stops and resumes playback if playback is running
+    pub fn set_rhythm(
+        &mut self,
+        beat_pattern: &BeatPattern,
+        beat_value: u16,
+        bpm: f64,
+    ) -> Result<(), String> {
+        if beat_pattern.pattern.is_empty() {
+            return Err("Beat pattern is empty, will not change anything".to_string());
+        }
+        if beat_value == 0 || !bpm.is_finite() || bpm <= 0.0 {
+            return Err("Beat value and bpm must be > 0".to_string());
+        }
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+
+        let previous = (self.beat_pattern.clone(), self.beat_value, self.bpm);
+        let previous_map = self.tempo_map.take();
+        self.beat_pattern = BeatPattern {
+            index: None,
+            ..beat_pattern.clone()
+        };
+        self.beat_value = beat_value;
+        self.bpm = bpm;
+
+        if restart && self.play_beat().is_err() {
+            (self.beat_pattern, self.beat_value, self.bpm) = previous;
+            self.tempo_map = previous_map;
+            Err("New rhythm does not seem to work, returning to previous rhythm".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
     /// Set pitches for accent and normal beat
     ///
     /// Stops and resumes playback if playback is running
@@ -321,24 +805,526 @@ impl BeatPlayer {
         Ok(())
     }
 
-    fn update_pattern_counter(&mut self) {
+    /// Set the audio output device, `None` selects the default device
+    ///
+    /// Stops and resumes playback if playback is running, with the previous device if the new one
+    /// fails
+    pub fn set_device(&mut self, device: Option<String>) -> Result<(), String> {
+        output_device(device.as_deref())?;
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+        let previous_device = std::mem::replace(&mut self.device, device);
+        if restart {
+            if let Err(err) = self.play_beat() {
+                self.device = previous_device;
+                let _ = self.play_beat();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Play without audio or with the audio device again
+    ///
+    /// Stops and resumes playback if playback is running, as before if that fails
+    pub fn set_silent(&mut self, silent: bool) -> Result<(), String> {
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+        let previous_silent = std::mem::replace(&mut self.silent, silent);
+        if restart {
+            if let Err(err) = self.play_beat() {
+                self.silent = previous_silent;
+                let _ = self.play_beat();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ring the terminal bell at every beat that is not a pause, also during playback
+    pub fn set_bell(&mut self, bell: bool) {
+        self.bell = bell;
+        let pattern = self.playback_pattern().pattern.clone();
+        if let Some(stream) = self.stream.as_mut() {
+            stream._bell = bell
+                .then(|| TerminalBell::start(stream.clock.clone(), stream.grid.clone(), pattern));
+        }
+    }
+
+    pub fn bell(&self) -> bool {
+        self.bell
+    }
+
+    /// Set the reference pitch of A4 that is used for note names
+    pub fn set_a4(&mut self, a4: f64) -> Result<(), String> {
+        if !(400.0..=480.0).contains(&a4) {
+            return Err(format!("Reference pitch {} out of range", a4));
+        }
+        self.a4 = a4;
+        Ok(())
+    }
+
+    /// Settings of the beat playback as entries of a settings file
+    ///
+    /// `apply_settings` restores them.
+    pub fn settings(&self) -> Vec<Entry> {
+        let mut settings = vec![
+            Entry::new("bpm", format_bpm(self.bpm)),
+            Entry::new("value", self.beat_value.to_string()),
+            Entry::new("pattern", self.beat_pattern.to_string()),
+            Entry::new("reference", self.a4.to_string()),
+        ];
+        for (voice, tone) in [("accent", &self.ac_beat), ("beat", &self.beat)] {
+            settings.extend([
+                Entry::new(&format!("{}.frequency", voice), tone.frequency.to_string()),
+                Entry::new(&format!("{}.length", voice), tone.length.to_string()),
+                Entry::new(&format!("{}.overtones", voice), tone.overtones.to_string()),
+            ]);
+        }
+        match &self.tuning.files {
+            Some((scl, kbm)) => settings.extend([
+                Entry::new("tuning.scl", scl.display().to_string()),
+                Entry::new(
+                    "tuning.kbm",
+                    kbm.as_ref()
+                        .map(|x| x.display().to_string())
+                        .unwrap_or_default(),
+                ),
+            ]),
+            None => settings.push(Entry::new("tuning", self.tuning.name.clone())),
+        }
+        settings.push(Entry::new("tuning.root", self.tuning.root_note()));
+        settings
+    }
+
+    /// Apply settings as returned by `settings`, settings that are not given stay unchanged
+    ///
+    /// Nothing is changed if a setting is invalid. Stops and resumes playback if playback is
+    /// running. The drone is not a setting, it is started and stopped like the playback.
+    pub fn apply_settings(&mut self, settings: &[Entry]) -> Result<(), String> {
+        let previous = (
+            self.bpm,
+            self.beat_value,
+            self.beat_pattern.clone(),
+            self.a4,
+            self.ac_beat.clone(),
+            self.beat.clone(),
+            self.tuning.clone(),
+        );
+        let (mut bpm, mut beat_value, mut beat_pattern, mut a4, mut ac_beat, mut beat, tuning) =
+            previous.clone();
+        let (mut temperament, mut scl, mut kbm, mut root) = (None, None, None, None);
+        for entry in settings {
+            let number = || {
+                entry
+                    .value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| x.is_finite())
+                    .ok_or_else(|| entry.invalid("not a number"))
+            };
+            let in_range = |min: f64, max: f64| -> Result<f64, String> {
+                let value = number()?;
+                if (min..=max).contains(&value) {
+                    Ok(value)
+                } else {
+                    Err(entry.invalid(&format!("must be within [{}; {}]", min, max)))
+                }
+            };
+            match entry.key.split_once('.') {
+                Some((voice @ ("accent" | "beat"), property)) => {
+                    let tone = if voice == "accent" {
+                        &mut ac_beat
+                    } else {
+                        &mut beat
+                    };
+                    match property {
+                        "frequency" => tone.frequency = in_range(20.0, 20000.0)?,
+                        "length" => tone.length = in_range(0.001, 1.0)?,
+                        "overtones" => tone.overtones = in_range(0.0, 16.0)? as u8,
+                        _ => return Err(entry.invalid("unknown setting")),
+                    }
+                }
+                _ => match entry.key.as_str() {
+                    "bpm" => bpm = in_range(f64::MIN_POSITIVE, 10000.0)?,
+                    "value" => beat_value = in_range(1.0, 256.0)? as u16,
+                    "pattern" => {
+                        beat_pattern = BeatPattern::try_from(entry.value.as_str())
+                            .map_err(|err| entry.invalid(&err))?;
+                        if beat_pattern.pattern.is_empty() {
+                            return Err(entry.invalid("pattern is empty"));
+                        }
+                    }
+                    "reference" => a4 = in_range(400.0, 480.0)?,
+                    "tuning" => {
+                        Tuning::temperament(&entry.value, tuning.root)
+                            .map_err(|err| entry.invalid(&err))?;
+                        temperament = Some(entry.value.clone());
+                    }
+                    "tuning.scl" => scl = Some(entry),
+                    "tuning.kbm" if entry.value.is_empty() => kbm = None,
+                    "tuning.kbm" => kbm = Some(entry.value.as_str()),
+                    "tuning.root" => {
+                        root = Some(
+                            parse_root(&entry.value)
+                                .ok_or_else(|| entry.invalid("not a root note"))?,
+                        )
+                    }
+                    _ => return Err(entry.invalid("unknown setting")),
+                },
+            }
+        }
+
+        let root = root.unwrap_or(tuning.root);
+        let tuning = match (scl, temperament) {
+            (Some(scl), _) => Tuning::load(scl.value.as_ref(), kbm.map(Path::new), root)
+                .map_err(|err| scl.invalid(&err))?,
+            (None, Some(temperament)) => Tuning::temperament(&temperament, root)?,
+            (None, None) => Tuning { root, ..tuning },
+        };
+
+        let restart = if self.is_playing() {
+            self.stop();
+            true
+        } else {
+            false
+        };
+        // the rhythm settings end following the tempo map
+        let previous_map = self.tempo_map.clone();
+        if settings
+            .iter()
+            .any(|entry| matches!(entry.key.as_str(), "bpm" | "value" | "pattern"))
+        {
+            self.tempo_map = None;
+        }
+        self.bpm = bpm;
+        self.beat_value = beat_value;
+        self.beat_pattern = beat_pattern;
+        self.a4 = a4;
+        self.ac_beat = ac_beat;
+        self.beat = beat;
+        self.tuning = tuning;
+
+        if restart && self.play_beat().is_err() {
+            self.tempo_map = previous_map;
+            (
+                self.bpm,
+                self.beat_value,
+                self.beat_pattern,
+                self.a4,
+                self.ac_beat,
+                self.beat,
+                self.tuning,
+            ) = previous;
+            Err("New settings do not seem to work, returning to previous settings".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Current bar:beat:tick position, `None` if playback is not running
+    pub fn position(&self) -> Option<MusicalPosition> {
+        let stream = self.stream.as_ref()?;
+        let frames = stream.clock.played_frames();
+        let beat = stream.grid.beat_at_frame(frames);
+        let beat_start = stream.grid.onset(beat);
+        let beat_length = stream.grid.onset(beat + 1) - beat_start;
+        let (bar, beat_in_bar) = self.playback_pattern().bar_and_beat(beat);
+        Some(MusicalPosition {
+            bar: (self.counter.bars + bar).saturating_sub(stream.bar_origin) + 1,
+            beat: beat_in_bar + 1,
+            tick: (frames - beat_start) * TICKS_PER_BEAT / beat_length,
+        })
+    }
+
+    /// Beat that is currently played, `None` if playback is not running
+    pub fn played_beat(&mut self) -> Option<PlayedBeat> {
+        self.update_timer();
+        let position = self.position()?;
+        let stream = self.stream.as_ref()?;
+        let frames = stream.clock.played_frames();
+        let beat = stream.grid.beat_at_frame(frames);
+        let onset = stream.grid.onset(beat);
+        let pattern = self.playback_pattern();
+        let bar = pattern.bar_and_beat(beat).0;
+        let time = |frames: u64| {
+            Duration::from_secs_f64(samples_to_time(frames as usize, stream.sample_rate))
+        };
+        Some(PlayedBeat {
+            position,
+            beats_in_bar: pattern.bars[(bar % pattern.bars.len() as u64) as usize] as u64,
+            beat_type: pattern.pattern[(beat % pattern.pattern.len() as u64) as usize].clone(),
+            since_onset: time(frames - onset),
+            length: time(stream.grid.onset(beat + 1) - onset),
+            bpm: match &self.tempo_map {
+                Some((map, _)) => map.sections[map.section_index(bar)].bpm,
+                None => self.bpm,
+            },
+        })
+    }
+
+    /// Beat of the pattern cycle that is currently played, `None` if playback is not running
+    fn current_beat(&self) -> Option<u64> {
+        let stream = self.stream.as_ref()?;
+        Some(stream.grid.beat_at_frame(stream.clock.played_frames()))
+    }
+
+    /// Current bar number while playing, number of played bars otherwise
+    pub fn bars(&self) -> u64 {
+        match self.position() {
+            Some(position) => position.bar,
+            None => self.counter.bars,
+        }
+    }
+
+    /// Time that the metronome has been playing since the last counter reset
+    pub fn elapsed(&self) -> Duration {
+        let mut elapsed = self.counter.elapsed;
         if let Some(stream) = &self.stream {
-            if self.beat_pattern.index.is_some() {
-                let elapsed_seconds = (Instant::now() - stream.start_time).as_secs_f64();
-                let beats_per_second = self.bpm as f64 / 60.0;
-                let played_beats = (elapsed_seconds * beats_per_second).floor() as usize;
-                self.beat_pattern.index = Some(played_beats % self.beat_pattern.pattern.len());
+            let frames = stream
+                .clock
+                .played_frames()
+                .saturating_sub(stream.frame_origin);
+            elapsed +=
+                Duration::from_secs_f64(samples_to_time(frames as usize, stream.sample_rate));
+        }
+        elapsed
+    }
+
+    /// Reset bar counter, position and elapsed time
+    ///
+    /// During playback the current bar becomes the first bar.
+    pub fn reset_counters(&mut self) {
+        let remaining = self.timer_remaining();
+        self.counter = PlaybackCounter::default();
+        let current_bar = self
+            .current_beat()
+            .map(|beat| self.playback_pattern().bar_and_beat(beat).0);
+        if let (Some(stream), Some(bar)) = (self.stream.as_mut(), current_bar) {
+            stream.bar_origin = bar;
+            stream.frame_origin = stream.clock.played_frames();
+        }
+        // the session timer keeps its remaining time
+        let limit = remaining.map(|remaining| match remaining {
+            TimerLimit::Time(time) => TimerLimit::Time(self.elapsed() + time),
+            TimerLimit::Bars(bars) => TimerLimit::Bars(self.completed_bars() + bars),
+        });
+        if let (Some(timer), Some(limit)) = (self.timer.as_mut(), limit) {
+            timer.limit = limit;
+        }
+        self.arm_timer();
+    }
+
+    /// Number of bars that have been played completely
+    fn completed_bars(&self) -> u64 {
+        match self.position() {
+            Some(position) => position.bar - 1,
+            None => self.counter.bars,
+        }
+    }
+
+    /// Set or remove the practice session timer
+    ///
+    /// A time limit counts from now on, a bar limit includes the current bar.
+    pub fn set_timer(&mut self, timer: Option<SessionTimer>) {
+        self.timer = timer.map(|mut timer| {
+            timer.limit = match timer.limit {
+                TimerLimit::Time(time) => TimerLimit::Time(self.elapsed() + time),
+                TimerLimit::Bars(bars) => TimerLimit::Bars(self.completed_bars() + bars),
+            };
+            timer
+        });
+        self.arm_timer();
+    }
+
+    /// Values of the status line placeholders, see `statusline::PLACEHOLDERS`
+    fn status_values(&mut self) -> HashMap<&'static str, String> {
+        self.update_timer();
+        self.update_pattern_counter();
+        let position = match self.position() {
+            Some(position) => position.to_string(),
+            None => "-".to_string(),
+        };
+        // a tempo map shows the current bar and section
+        let (pattern, beat_value, bpm, section) = match &self.tempo_map {
+            Some((map, pattern)) => {
+                let (bar, beat) = pattern.bar_and_beat(pattern.index.unwrap_or(0) as u64);
+                let start = pattern.bar_onset(bar) as usize;
+                let bar_pattern = BeatPattern {
+                    index: pattern.index.map(|_| beat as usize),
+                    ..BeatPattern::new(
+                        pattern.pattern[start..start + pattern.bars[bar as usize]].to_vec(),
+                    )
+                };
+                let index = map.section_index(bar);
+                let section = &map.sections[index];
+                (
+                    bar_pattern.to_string_with_current_beat(),
+                    section.beat_value,
+                    section.bpm,
+                    format!("{} {}/{}", map.name, index + 1, map.sections.len()),
+                )
             }
+            None => (
+                self.beat_pattern.to_string_with_current_beat(),
+                self.beat_value,
+                self.bpm,
+                String::new(),
+            ),
         };
+        let drone = match self.drone.is_playing() {
+            true => {
+                let notes: Vec<String> = self
+                    .drone
+                    .frequencies
+                    .iter()
+                    .map(|&frequency| note_name(frequency, self.a4))
+                    .collect();
+                notes.join(" ")
+            }
+            false => String::new(),
+        };
+        let device = match &self.stream {
+            Some(StreamWrapper {
+                output: PlaybackOutput::Silent { .. },
+                ..
+            }) => "silent".to_string(),
+            _ => self.device.clone().unwrap_or_else(|| "default".to_string()),
+        };
+        HashMap::from([
+            ("pattern", pattern),
+            ("value", beat_value.to_string()),
+            ("bpm", format_bpm(bpm)),
+            ("accent_hz", format!("{:.3}", self.ac_beat.frequency)),
+            ("beat_hz", format!("{:.3}", self.beat.frequency)),
+            ("accent_note", note_name(self.ac_beat.frequency, self.a4)),
+            ("note", note_name(self.beat.frequency, self.a4)),
+            ("bar", self.bars().to_string()),
+            ("pos", position),
+            ("elapsed", format_elapsed(self.elapsed())),
+            ("map", section),
+            ("tuning", self.tuning.to_string()),
+            (
+                "timer",
+                self.timer_remaining()
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("drone", drone),
+            ("device", device),
+        ])
+    }
+
+    /// Time or bars until the practice session timer stops the playback
+    pub fn timer_remaining(&self) -> Option<TimerLimit> {
+        let timer = self.timer.as_ref()?;
+        Some(match timer.limit {
+            TimerLimit::Time(end) => TimerLimit::Time(end.saturating_sub(self.elapsed())),
+            TimerLimit::Bars(end) => TimerLimit::Bars(end.saturating_sub(self.completed_bars())),
+        })
+    }
+
+    /// Let the audio callback know when the session of the current playback run ends
+    fn arm_timer(&self) {
+        let stream = match &self.stream {
+            Some(stream) => stream,
+            None => return,
+        };
+        stream.clock.clear_end();
+        if let Some(timer) = &self.timer {
+            let end_frame = match timer.limit {
+                TimerLimit::Time(end) => {
+                    let remaining = end.saturating_sub(self.elapsed()).as_secs_f64();
+                    stream.clock.frames() + time_in_samples(remaining, stream.sample_rate) as u64
+                }
+                TimerLimit::Bars(end) => {
+                    let bars = end.saturating_sub(self.counter.bars);
+                    let bar = stream.bar_origin + bars;
+                    stream.grid.onset(self.playback_pattern().bar_onset(bar))
+                }
+            };
+            let fade_frames = time_in_samples(timer.fade.as_secs_f64(), stream.sample_rate);
+            stream.clock.set_end(end_frame, fade_frames as u64);
+        }
     }
 
-    fn _fill_playback_buffer(
+    /// Stop the playback when the audio callback has finished the session
+    fn update_timer(&mut self) {
+        let finished = match &self.stream {
+            Some(stream) => stream.clock.is_finished(),
+            None => false,
+        };
+        if finished {
+            self.stop();
+            self.timer = None;
+        }
+    }
+
+    /// Beats that were played since the last call
+    fn beat_events(&mut self) -> Vec<String> {
+        let (stream, current_beat) = match (&self.stream, self.current_beat()) {
+            (Some(stream), Some(beat)) => (stream, beat),
+            _ => {
+                self.last_beat = None;
+                return Vec::new();
+            }
+        };
+        let first_beat = match self.last_beat {
+            Some(last_beat)
+                if last_beat <= current_beat && current_beat - last_beat <= MAX_REPORTED_BEATS =>
+            {
+                last_beat + 1
+            }
+            _ => current_beat,
+        };
+        let events = (first_beat..=current_beat)
+            .map(|beat| {
+                let onset = stream.grid.onset(beat);
+                let time = self.counter.elapsed.as_secs_f64()
+                    + samples_to_time(
+                        onset.saturating_sub(stream.frame_origin) as usize,
+                        stream.sample_rate,
+                    );
+                let pattern = self.playback_pattern();
+                let (bar, beat_in_bar) = pattern.bar_and_beat(beat);
+                let beat_type = &pattern.pattern[(beat % pattern.pattern.len() as u64) as usize];
+                format!(
+                    "beat {:.3} {}:{} {}",
+                    time,
+                    (self.counter.bars + bar).saturating_sub(stream.bar_origin) + 1,
+                    beat_in_bar + 1,
+                    beat_type
+                )
+            })
+            .collect();
+        self.last_beat = Some(current_beat);
+        events
+    }
+
+    fn update_pattern_counter(&mut self) {
+        let current_beat = self.current_beat();
+        let pattern = self.playback_pattern_mut();
+        if pattern.index.is_some() {
+            if let Some(beat) = current_beat {
+                pattern.index = Some((beat % pattern.pattern.len() as u64) as usize);
+            }
+        };
+    }
+
+    fn _create_renderer(
         &self,
         sample_rate: f64,
         channels: usize,
-    ) -> Result<AudioSignal<f32>, &'static str> {
-        // Create the playback buffer over which the output loops
-        // Use self.beat and silence to fill the buffer
+        clock: PlaybackClock,
+    ) -> Result<(BeatRenderer, BeatGrid), &'static str> {
         if self.beat.frequency <= 0.0 || self.ac_beat.frequency <= 0.0 {
             return Err("Tone Configuration not applicable");
         }
@@ -356,84 +1342,68 @@ impl BeatPlayer {
         beat.fade_in_out(fade_time, fade_time).unwrap();
         ac_beat.fade_in_out(fade_time, fade_time).unwrap();
 
-        let beats_per_minute = self.bpm as f64 * self.beat_value as f64 / BASE_BEAT_VALUE as f64;
-        let samples_per_beat = ((60.0 * sample_rate) / beats_per_minute).round() as isize;
+        let grid = match &self.tempo_map {
+            Some((map, _)) => map
+                .grid(sample_rate)
+                .map_err(|_| "The tempo map has no beats")?,
+            None => BeatGrid::constant(samples_per_beat(self.bpm, self.beat_value, sample_rate)),
+        };
+        let samples_per_beat = grid.min_samples_per_beat();
 
-        let silence_samples = samples_per_beat - beat.signal.len() as isize;
-        if silence_samples < 0 {
+        if beat.signal.len() as f64 > samples_per_beat.floor() {
             return Err("Beat to long to play at current bpm");
         }
-
-        let ac_silence_samples = samples_per_beat - ac_beat.signal.len() as isize;
-        if ac_silence_samples < 0 {
+        if ac_beat.signal.len() as f64 > samples_per_beat.floor() {
             return Err("Accentuated beat to long to play at current bpm");
         }
 
-        // prepare the playback buffer
-        let (ac_beat_count, beat_count, pause_count) = {
-            let mut a = 0;
-            let mut b = 0;
-            let mut c = 0;
-            for bpt in &self.beat_pattern.pattern {
-                match bpt {
-                    BeatPatternType::Accent => a += 1,
-                    BeatPatternType::Beat => b += 1,
-                    BeatPatternType::Pause => c += 1,
-                }
-            }
-            (a, b, c)
+        let chime = match &self.timer {
+            Some(timer) if timer.chime => Some(self._chime_signal(sample_rate, channels)),
+            _ => None,
         };
 
-        let playback_buffer_samples = ac_beat_count
-            * (ac_beat.signal.len() + ac_silence_samples as usize)
-            + beat_count * (beat.signal.len() + silence_samples as usize)
-            + pause_count * (samples_per_beat as usize);
+        let renderer = BeatRenderer::new(
+            beat.signal,
+            ac_beat.signal,
+            self.playback_pattern().pattern.clone(),
+            grid.clone(),
+            chime,
+            channels,
+            clock,
+        );
+        Ok((renderer, grid))
+    }
 
-        let mut playback_buffer = AudioSignal {
-            signal: Vec::with_capacity(playback_buffer_samples),
+    /// Short arpeggio that is played at the end of a practice session
+    fn _chime_signal(&self, sample_rate: f64, channels: usize) -> AudioSignal<f32> {
+        let mut chime = AudioSignal {
+            signal: Vec::new(),
             index: 0,
             tone: ToneConfiguration {
                 frequency: 0.0,
                 sample_rate,
-                length: samples_to_time(playback_buffer_samples, sample_rate),
+                length: 0.0,
                 overtones: 0,
                 channels: 1,
             },
         };
-
-        for beat_type in &self.beat_pattern.pattern {
-            match beat_type {
-                BeatPatternType::Accent => {
-                    playback_buffer
-                        .signal
-                        .extend_from_slice(&ac_beat.signal[0..]);
-                    for _ in 0..ac_silence_samples {
-                        playback_buffer.signal.push(0f32);
-                    }
-                }
-                BeatPatternType::Beat => {
-                    playback_buffer.signal.extend_from_slice(&beat.signal[0..]);
-                    for _ in 0..silence_samples {
-                        playback_buffer.signal.push(0f32);
-                    }
-                }
-                BeatPatternType::Pause => {
-                    for _ in 0..samples_per_beat {
-                        playback_buffer.signal.push(0f32);
-                    }
-                }
-            }
+        for (semitone, length) in [(0.0, 0.15), (4.0, 0.15), (7.0, 0.15), (12.0, 0.8)] {
+            let mut tone = AudioSignal::generate_tone(&ToneConfiguration {
+                frequency: frequency_relative_semitone_equal_temperament(
+                    self.beat.frequency,
+                    semitone,
+                ),
+                sample_rate,
+                length,
+                overtones: 1,
+                channels: 1,
+            });
+            tone.fade_in_out(0.01, length * 0.8).unwrap();
+            chime.signal.extend(tone.signal);
         }
-
-        playback_buffer = {
-            if channels > 1 {
-                playback_buffer.channels_from_mono(channels).unwrap()
-            } else {
-                playback_buffer
-            }
-        };
-
-        Ok(playback_buffer)
+        chime.tone.length = samples_to_time(chime.signal.len(), sample_rate);
+        chime *= 0.6;
+        chime.channels_from_mono(channels).unwrap()
     }
 
     pub fn play_beat(&mut self) -> Result<(), String> {
@@ -443,105 +1413,310 @@ impl BeatPlayer {
             return Err("Cannot start beat playback, it is already running".into());
         }
 
-        let audio_host = cpal::default_host();
-        let device = match audio_host.default_output_device() {
-            Some(x) => x,
-            None => return Err(format!("No audio device for {:?}", audio_host.id())),
+        let audio = match self.silent {
+            true => None,
+            false => match output_device(self.device.as_deref()) {
+                Ok(audio) => Some(audio),
+                // without an audio device the playback falls back to the silent mode
+                Err(_) if self.device.is_none() && !has_default_output_device() => None,
+                Err(err) => return Err(err),
+            },
         };
-        let default_config = {
-            match device.default_output_config() {
-                Ok(x) => x,
-                Err(y) => {
-                    return Err(format!(
-                        "No output configuration on default output device: {:?}",
-                        y
-                    ))
-                }
+
+        let (sample_rate, channels) = match &audio {
+            Some((_, config)) => (config.sample_rate().0 as f64, config.channels() as usize),
+            None => (SOFTWARE_SAMPLE_RATE, 1),
+        };
+        let clock = PlaybackClock::new();
+        let (renderer, grid) = self._create_renderer(sample_rate, channels, clock.clone())?;
+        // a tempo map starts again, otherwise the song continues with the next counted bar
+        let (pulses, song_position) = match (&self.midi_clock, &self.tempo_map) {
+            (None, _) => (None, 0),
+            (Some(_), Some((map, _))) => (Some(map.pulse_grid(grid.clone())?), 0),
+            (Some(_), None) => (
+                Some(PulseGrid::constant(grid.clone(), self.beat_value)),
+                self.beat_pattern.bar_onset(self.counter.bars) * 16 / self.beat_value as u64,
+            ),
+        };
+        let output = match audio {
+            Some((device, config)) => {
+                PlaybackOutput::Audio(create_cpal_stream(device, config, renderer)?)
             }
+            None => PlaybackOutput::Silent {
+                _clock: SoftwareClock::start(renderer),
+            },
         };
-
-        let playback_buffer = self._fill_playback_buffer(
-            default_config.sample_rate().0 as f64,
-            default_config.channels() as usize,
-        )?;
-
+        let bell = self.bell.then(|| {
+            TerminalBell::start(
+                clock.clone(),
+                grid.clone(),
+                self.playback_pattern().pattern.clone(),
+            )
+        });
         self.stream = Some(StreamWrapper {
-            stream: create_cpal_stream(device, default_config, playback_buffer)?,
-            start_time: Instant::now(),
+            output,
+            clock,
+            sample_rate,
+            grid,
+            bar_origin: 0,
+            frame_origin: 0,
+            _clock_sender: None,
+            _bell: bell,
         });
-        self.beat_pattern.index = Some(0);
+        match &mut self.tempo_map {
+            Some((_, pattern)) => pattern.index = Some(0),
+            None => self.beat_pattern.index = Some(0),
+        }
+        self.arm_timer();
 
-        match self.stream.as_mut().unwrap().stream.play() {
-            Ok(_) => (),
-            Err(_) => {
+        if let PlaybackOutput::Audio(stream) = &self.stream.as_ref().unwrap().output {
+            if stream.play().is_err() {
                 self.stream = None;
                 return Err("Something went wrong with beat playback".into());
             }
-        };
+        }
+
+        if let (Some(transport), Some(pulses)) = (&self.midi_clock, pulses) {
+            let stream = self.stream.as_mut().unwrap();
+            stream._clock_sender = Some(ClockSender::start(
+                transport.clone(),
+                stream.clock.clone(),
+                pulses,
+                sample_rate,
+                song_position,
+            ));
+        }
+        self.publish_timeline();
 
         // everything was fine fine
         Ok(())
     }
 }
 
-fn create_cpal_stream(
-    device: cpal::Device,
-    config: cpal::SupportedStreamConfig,
-    playback_buffer: AudioSignal<f32>,
-) -> Result<Stream, String> {
-    let sampletype = config.sample_format();
-    let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
-    let my_config = config.into();
-
-    //TODO: unify these lambdas somehow
-    let stream = match sampletype {
-        SampleFormat::F32 => {
-            let mut playback_buffer: AudioSignal<f32> = playback_buffer;
-            device.build_output_stream(
-                &my_config,
-                move |data, _| {
-                    for sample in data.iter_mut() {
-                        *sample = playback_buffer.get_next_sample();
-                    }
-                },
-                err_fn,
-                None,
-            )
-        }
-        SampleFormat::I16 => {
-            let mut playback_buffer: AudioSignal<i16> = playback_buffer.into();
-            device.build_output_stream(
-                &my_config,
-                move |data, _| {
-                    for sample in data.iter_mut() {
-                        *sample = playback_buffer.get_next_sample();
-                    }
-                },
-                err_fn,
-                None,
-            )
-        }
-        SampleFormat::U16 => {
-            let mut playback_buffer: AudioSignal<u16> = playback_buffer.into();
-            device.build_output_stream(
-                &my_config,
-                move |data, _| {
-                    for sample in data.iter_mut() {
-                        *sample = playback_buffer.get_next_sample();
-                    }
-                },
-                err_fn,
-                None,
-            )
+/// Format the bpm with up to three decimal places
+pub fn format_bpm(bpm: f64) -> String {
+    let formatted = format!("{:.3}", bpm);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod test_beatplayer {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn test_pattern_grammar() {
+        let pattern = BeatPattern::try_from("!+++").unwrap();
+        assert_eq!(pattern.bars, vec![4]);
+        assert_eq!(pattern.to_string(), "!+++");
+
+        let pattern = BeatPattern::try_from("| !+++ | !++ |  # 7/4 as 4+3").unwrap();
+        assert_eq!(pattern.bars, vec![4, 3]);
+        assert_eq!(pattern.to_string(), "!+++|!++");
+
+        let pattern = BeatPattern::try_from("(!+.)x2 (!+|)x2").unwrap();
+        assert_eq!(pattern.to_string(), "!+.!+.!+|!+");
+        assert_eq!(pattern.bars, vec![8, 2]);
+
+        let pattern = BeatPattern::try_from("((!+)x2|)x3").unwrap();
+        assert_eq!(pattern.bars, vec![4, 4, 4]);
+
+        assert_eq!(
+            BeatPattern::try_from("!+a+").unwrap_err(),
+            "Column 3: unexpected character \"a\", expected one of `!+.|()#`"
+        );
+        assert_eq!(
+            BeatPattern::try_from("!+ (++").unwrap_err(),
+            "Column 4: \"(\" is never closed"
+        );
+        assert_eq!(
+            BeatPattern::try_from("!++)").unwrap_err(),
+            "Column 4: \")\" without \"(\""
+        );
+        assert_eq!(
+            BeatPattern::try_from("(!+)x").unwrap_err(),
+            "Column 6: expected a repeat count > 0"
+        );
+        assert!(BeatPattern::try_from("(!)x100000").is_err());
+    }
+
+    #[test]
+    fn test_pattern_repeat_limits() {
+        for pattern in [
+            "(!+)x9223372036854775808",
+            "(!+)x18446744073709551617",
+            "(!+)x4097",
+        ] {
+            assert_eq!(
+                BeatPattern::try_from(pattern).unwrap_err(),
+                "Column 6: repeat count is too large"
+            );
         }
-        _ => todo!(),
-    };
+        assert_eq!(
+            BeatPattern::try_from("(!+)x2049").unwrap_err(),
+            "Column 1: pattern is too long"
+        );
+        assert_eq!(
+            BeatPattern::try_from("(!+)x2048").unwrap().pattern.len(),
+            4096
+        );
+    }
+
+    #[test]
+    fn test_pattern_bars() {
+        let pattern = BeatPattern::try_from("!+++|!++").unwrap();
+        assert_eq!(pattern.bar_and_beat(0), (0, 0));
+        assert_eq!(pattern.bar_and_beat(5), (1, 1));
+        assert_eq!(pattern.bar_and_beat(7), (2, 0));
+        assert_eq!(pattern.bar_and_beat(12), (3, 1));
+        assert_eq!(pattern.bar_onset(0), 0);
+        assert_eq!(pattern.bar_onset(1), 4);
+        assert_eq!(pattern.bar_onset(3), 11);
+    }
+
+    /// Silent player at 60 bpm in 4/4, a bar lasts 4 s
+    fn silent_player() -> BeatPlayer {
+        let tone = ToneConfiguration {
+            sample_rate: SOFTWARE_SAMPLE_RATE,
+            frequency: 440.0,
+            overtones: 1,
+            length: 0.05,
+            channels: 1,
+        };
+        let pattern = BeatPattern::try_from("!+++").unwrap();
+        let mut bp = BeatPlayer::new(60.0, 4, tone.clone(), tone, pattern);
+        bp.silent = true;
+        bp
+    }
+
+    /// Jump the running playback `seconds` ahead
+    fn skip(bp: &BeatPlayer, seconds: f64) {
+        let stream = bp.stream.as_ref().unwrap();
+        stream.clock.advance((seconds * stream.sample_rate) as u64);
+    }
+
+    #[test]
+    fn test_counters_across_restarts() {
+        let mut bp = silent_player();
+        bp.play_beat().unwrap();
+        skip(&bp, 10.0);
+        assert_eq!(bp.position().unwrap().bar, 3);
+        assert_eq!(bp.bars(), 3);
+
+        // the interrupted bar is played again and not counted
+        bp.stop();
+        assert_eq!(bp.bars(), 2);
+        assert!(bp.elapsed() >= Duration::from_secs(10));
+        bp.play_beat().unwrap();
+        assert_eq!(bp.position().unwrap().bar, 3);
+
+        // a setter restarts the playback without counting a bar
+        skip(&bp, 1.0);
+        assert!(bp.set_bpm(120.0));
+        assert_eq!(bp.position().unwrap().bar, 3);
+        assert_eq!(bp.position().unwrap().beat, 1);
+        assert!(bp.elapsed() >= Duration::from_secs(11));
+        bp.stop();
+    }
+
+    #[test]
+    fn test_midi_song_position() {
+        use crate::midiclock::{Loopback, MidiTransport};
+        use std::sync::Arc;
+        let (sender, mut receiver) = Loopback::pair();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let messages = received.clone();
+        receiver
+            .set_receiver(Box::new(move |message| {
+                if message[0] != 0xf8 {
+                    messages.lock().unwrap().push(message.to_vec())
+                }
+            }))
+            .unwrap();
+        let mut bp = silent_player();
+        bp.set_midi_clock(Some(Arc::new(Mutex::new(Box::new(sender)))))
+            .unwrap();
+        bp.play_beat().unwrap();
+        // a restart in the third bar continues at the start of the third bar, sixteenth 32
+        skip(&bp, 9.0);
+        assert!(bp.set_bpm(120.0));
+        bp.stop();
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                vec![0xf2, 0, 0],
+                vec![0xfa],
+                vec![0xfc],
+                vec![0xf2, 32, 0],
+                vec![0xfb],
+                vec![0xfc]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bar_timer_while_playing() {
+        let mut bp = silent_player();
+        bp.play_beat().unwrap();
+        skip(&bp, 10.0);
+        bp.set_timer(Some(SessionTimer::try_from("16 bars").unwrap()));
+        assert_eq!(bp.timer_remaining(), Some(TimerLimit::Bars(16)));
+        skip(&bp, 4.0);
+        assert_eq!(bp.timer_remaining(), Some(TimerLimit::Bars(15)));
+        bp.stop();
+        assert_eq!(bp.timer_remaining(), Some(TimerLimit::Bars(15)));
+    }
+
+    #[test]
+    fn test_settings() {
+        let tone = ToneConfiguration {
+            sample_rate: 48000.0,
+            frequency: 440.0,
+            overtones: 1,
+            length: 0.05,
+            channels: 1,
+        };
+        let pattern = BeatPattern::try_from("!+++").unwrap();
+        let mut bp = BeatPlayer::new(100.0, 4, tone.clone(), tone.clone(), pattern.clone());
+        let mut settings = bp.settings();
+        settings[0].value = "93.5".to_string();
+        settings[2].value = "!++|!+".to_string();
+        bp.apply_settings(&settings).unwrap();
+        assert_eq!(bp.bpm, 93.5);
+        assert_eq!(bp.beat_pattern.bars, vec![3, 2]);
+        assert_eq!(bp.settings(), settings);
+
+        // snapshots keep the tuning
+        bp.tuning = Tuning::temperament("just", 62).unwrap();
+        let settings = bp.settings();
+        assert_eq!(
+            settings[settings.len() - 2],
+            Entry::new("tuning", "just".to_string())
+        );
+        assert_eq!(
+            settings[settings.len() - 1],
+            Entry::new("tuning.root", "D4".to_string())
+        );
+        let mut loaded = BeatPlayer::new(100.0, 4, tone.clone(), tone, pattern);
+        loaded.apply_settings(&settings).unwrap();
+        assert_eq!(loaded.tuning, bp.tuning);
+
+        let dir = TestDir::new();
+        let scl = dir.join("fifths.scl");
+        std::fs::write(&scl, "Fifths\n2\n3/2\n2/1\n").unwrap();
+        bp.tuning = Tuning::load(&scl, None, 60).unwrap();
+        loaded.apply_settings(&bp.settings()).unwrap();
+        assert_eq!(loaded.tuning, bp.tuning);
 
-    match stream {
-        Ok(stream) => Ok(stream),
-        Err(x) => Err(format!(
-            "Streamconfig {:?} is not supported, got error: {:?}",
-            my_config, x
-        )),
+        let mut invalid = Entry::new("beat.frequency", "5".to_string());
+        invalid.line = 3;
+        assert_eq!(
+            bp.apply_settings(&[Entry::new("bpm", "60".to_string()), invalid]),
+            Err("Line 3: Invalid beat.frequency \"5\": must be within [20; 20000]".to_string())
+        );
+        assert_eq!(bp.bpm, 93.5);
     }
 }
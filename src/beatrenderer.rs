@@ -0,0 +1,103 @@
+use cpal::{FromSample, Sample};
+
+use crate::{
+    audiooutput::AudioRenderer,
+    audiosignal::AudioSignal,
+    beatplayer::BeatPatternType,
+    playbackclock::{BeatGrid, PlaybackClock},
+};
+
+/// Fills the output buffers of the audio device
+///
+/// Plays the beat pattern in a loop until the end of the session, which is faded out, and plays
+/// the chime after it. Every beat starts at the sample that is nearest to its exact position, so
+/// there is no drift even if a beat does not last an integer number of samples.
+pub struct BeatRenderer {
+    /// Mono signal of the normal beat
+    beat: Vec<f32>,
+    /// Mono signal of the accentuated beat
+    ac_beat: Vec<f32>,
+    pattern: Vec<BeatPatternType>,
+    grid: BeatGrid,
+    /// Interleaved signal that is played once after the end of the session
+    chime: Option<AudioSignal<f32>>,
+    channels: usize,
+    clock: PlaybackClock,
+    /// Index of the current beat since the start of the playback
+    beat_index: u64,
+    beat_start: u64,
+    next_beat_start: u64,
+}
+
+impl BeatRenderer {
+    pub fn new(
+        beat: Vec<f32>,
+        ac_beat: Vec<f32>,
+        pattern: Vec<BeatPatternType>,
+        grid: BeatGrid,
+        chime: Option<AudioSignal<f32>>,
+        channels: usize,
+        clock: PlaybackClock,
+    ) -> BeatRenderer {
+        BeatRenderer {
+            beat,
+            ac_beat,
+            pattern,
+            chime,
+            channels,
+            clock,
+            beat_index: 0,
+            beat_start: 0,
+            next_beat_start: grid.onset(1),
+            grid,
+        }
+    }
+
+    fn next_beat_sample(&mut self, frame: u64) -> f32 {
+        // the next beat or a jump of the clock
+        if frame < self.beat_start || frame >= self.next_beat_start {
+            self.beat_index = self.grid.beat_at_frame(frame);
+            self.beat_start = self.grid.onset(self.beat_index);
+            self.next_beat_start = self.grid.onset(self.beat_index + 1);
+        }
+        let beat = match self.pattern[(self.beat_index % self.pattern.len() as u64) as usize] {
+            BeatPatternType::Accent => &self.ac_beat,
+            BeatPatternType::Beat => &self.beat,
+            BeatPatternType::Pause => return 0.0,
+        };
+        let index = (frame - self.beat_start) as usize;
+        beat.get(index).copied().unwrap_or(0.0)
+    }
+
+    fn next_chime_sample(&mut self) -> f32 {
+        match self.chime.as_mut() {
+            Some(chime) if chime.index < chime.signal.len() => {
+                chime.index += 1;
+                chime.signal[chime.index - 1]
+            }
+            _ => {
+                self.clock.finish();
+                0.0
+            }
+        }
+    }
+}
+
+impl AudioRenderer for BeatRenderer {
+    /// Render the next frames into `data` and advance the clock accordingly
+    fn render<T: Sample + FromSample<f32>>(&mut self, data: &mut [T]) {
+        let end_frame = self.clock.end_frame();
+        let frames = self.clock.frames()..;
+        for (frame, output_frame) in frames.zip(data.chunks_mut(self.channels)) {
+            if frame < end_frame {
+                let sample = T::from_sample(self.next_beat_sample(frame) * self.clock.gain(frame));
+                output_frame.fill(sample);
+            } else {
+                for sample in output_frame.iter_mut() {
+                    *sample = T::from_sample(self.next_chime_sample());
+                }
+            }
+        }
+        self.clock.advance((data.len() / self.channels) as u64);
+    }
+}
@@ -0,0 +1,282 @@
+use std::path::PathBuf;
+
+use crate::remotecontrol::default_socket_path;
+
+/// Help text for the command line options
+pub const USAGE: &str = "\
+Usage: mnomer [OPTIONS]
+       mnomer play [OPTIONS]   same as --headless
+
+Options:
+  --preset <name>       start with a preset, see \"preset list\"
+  --bpm <bpm>           beats per minute, fractional values are allowed
+  --pattern <pattern>   beat pattern like \"!+++\" or \"!+++|!++\"
+  --value <value>       beat value, 4 means the beat is 1/4
+  --pitch <pitches>     accentuated and normal beat pitch like \"D5 A4\" or \"587,440\"
+  --device <name>       audio output device whose name contains <name>
+  --start               start the playback immediately
+  --silent              play without audio, e.g. for the visual view, which is also done when
+                        there is no audio device
+  --duration <duration> stop the playback after a duration like \"90\", \"5m\" or \"16 bars\"
+  --headless            only play, without the interactive prompt, until SIGINT, SIGTERM or
+                        the end of --duration
+  --log-beats           print every beat in headless and script mode
+  --script <file>       execute the REPL commands of <file>, \"-\" for stdin, which is also
+                        used when it is not a terminal
+  --socket[=<path>]     accept remote commands on a Unix domain socket, by default
+                        $XDG_RUNTIME_DIR/mnomer-$USER.sock
+  --tcp <port>          accept remote commands that do not access files on localhost TCP
+                        port <port>
+  --osc <port>          receive OSC messages like /mnomer/bpm on localhost UDP port <port>
+  --osc-send <target>   send /mnomer/beat messages to a port or host:port, needs --osc
+  --midi-clock <mode>   \"send\" MIDI clock or \"follow\" an external clock on the virtual MIDI
+                        port \"mnomer\"
+  --sync <role>         \"lead\" or \"follow\" the tempo and beats of other instances on the
+                        local network
+  --sync-port <port>    UDP port of the network sync, 47447 by default, needs --sync
+  -h, --help            show this help
+  -V, --version         show the version";
+
+/// Options of a normal run
+#[derive(Debug, Default, PartialEq)]
+pub struct CliOptions {
+    /// REPL command lines that are applied before the run
+    pub command_lines: Vec<String>,
+    /// Play without the interactive REPL
+    pub headless: bool,
+    /// Print every beat in headless mode
+    pub log_beats: bool,
+    /// File with REPL commands, `-` for stdin
+    pub script: Option<String>,
+    /// Path of the Unix domain socket for remote commands
+    pub socket: Option<PathBuf>,
+    /// Localhost TCP port for remote commands
+    pub tcp_port: Option<u16>,
+    /// Localhost UDP port for OSC messages
+    pub osc_port: Option<u16>,
+    /// Port or host:port that receives the OSC beat messages
+    pub osc_send: Option<String>,
+    /// Send MIDI clock or follow an external one
+    pub midi_clock: Option<MidiClockMode>,
+    /// Lead or follow other instances on the local network
+    pub sync: Option<SyncMode>,
+    /// UDP port of the network sync
+    pub sync_port: Option<u16>,
+}
+
+/// Role of mnomer in MIDI clock synchronization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiClockMode {
+    Send,
+    Follow,
+}
+
+/// Role of mnomer in the network sync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    Lead,
+    Follow,
+}
+
+/// What the command line asks for
+#[derive(Debug, PartialEq)]
+pub enum CliAction {
+    Run(CliOptions),
+    Help,
+    Version,
+}
+
+/// Options that set the initial settings, in the order in which they are applied
+const SETTING_OPTIONS: [&str; 6] = ["preset", "bpm", "pattern", "value", "pitch", "device"];
+
+/// Parse the command line arguments without the program name
+///
+/// The options are translated to REPL command lines, so that they are validated like the REPL
+/// commands. Options accept their value as next argument or after `=`.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliAction, String> {
+    let mut settings: Vec<Option<String>> = vec![None; SETTING_OPTIONS.len()];
+    let mut options = CliOptions::default();
+    let mut start = false;
+    let mut silent = false;
+    let mut duration = None;
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "play").is_some() {
+        options.headless = true;
+    }
+    while let Some(arg) = args.next() {
+        let (option, value) = match arg.split_once('=') {
+            Some((option, value)) => (option.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        match option.as_str() {
+            "-h" | "--help" => return Ok(CliAction::Help),
+            "-V" | "--version" => return Ok(CliAction::Version),
+            "--start" => start = true,
+            "--silent" => silent = true,
+            "--headless" => options.headless = true,
+            "--log-beats" => options.log_beats = true,
+            // the value is optional and therefore only accepted after `=`
+            "--socket" => {
+                options.socket = Some(match value {
+                    Some(path) => path.into(),
+                    None => default_socket_path(),
+                })
+            }
+            "--duration" | "--script" | "--tcp" | "--osc" | "--osc-send" | "--midi-clock"
+            | "--sync" | "--sync-port" => {
+                let value = match value.or_else(|| args.next()) {
+                    Some(value) if !value.is_empty() => value,
+                    _ => return Err(format!("Option \"{}\" needs a value", option)),
+                };
+                match option.as_str() {
+                    "--duration" => duration = Some(value),
+                    "--script" => options.script = Some(value),
+                    "--osc-send" => options.osc_send = Some(value),
+                    "--midi-clock" => {
+                        options.midi_clock = Some(match value.as_str() {
+                            "send" => MidiClockMode::Send,
+                            "follow" => MidiClockMode::Follow,
+                            _ => return Err(format!("Unknown MIDI clock mode \"{}\"", value)),
+                        })
+                    }
+                    "--sync" => {
+                        options.sync = Some(match value.as_str() {
+                            "lead" => SyncMode::Lead,
+                            "follow" => SyncMode::Follow,
+                            _ => return Err(format!("Unknown sync role \"{}\"", value)),
+                        })
+                    }
+                    _ => match value.parse::<u16>() {
+                        Ok(port) if port > 0 && option == "--tcp" => options.tcp_port = Some(port),
+                        Ok(port) if port > 0 && option == "--sync-port" => {
+                            options.sync_port = Some(port)
+                        }
+                        Ok(port) if port > 0 => options.osc_port = Some(port),
+                        _ => return Err(format!("\"{}\" is not a port", value)),
+                    },
+                }
+            }
+            _ => {
+                let index = option
+                    .strip_prefix("--")
+                    .and_then(|name| SETTING_OPTIONS.iter().position(|x| *x == name))
+                    .ok_or_else(|| format!("Unknown option \"{}\"", option))?;
+                let value = match value.or_else(|| args.next()) {
+                    Some(value) if !value.is_empty() => value,
+                    _ => return Err(format!("Option \"{}\" needs a value", option)),
+                };
+                settings[index] = Some(match SETTING_OPTIONS[index] {
+                    "pitch" => value.replace(',', " "),
+                    _ => value,
+                });
+            }
+        }
+    }
+
+    if options.osc_send.is_some() && options.osc_port.is_none() {
+        return Err("Option \"--osc-send\" needs \"--osc\"".to_string());
+    }
+    if options.sync_port.is_some() && options.sync.is_none() {
+        return Err("Option \"--sync-port\" needs \"--sync\"".to_string());
+    }
+
+    options.command_lines = SETTING_OPTIONS
+        .iter()
+        .zip(settings)
+        .filter_map(|(name, value)| Some(format!("{} {}", name, value?)))
+        .collect();
+    if silent {
+        options.command_lines.push("silent on".to_string());
+    }
+    if let Some(duration) = duration {
+        options.command_lines.push(format!("timer {}", duration));
+    }
+    if start || options.headless {
+        options.command_lines.push("start".to_string());
+    }
+    Ok(CliAction::Run(options))
+}
+
+#[cfg(test)]
+mod test_cli {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliAction, String> {
+        parse_args(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let run = |command_lines: &[&str], headless, log_beats| {
+            Ok(CliAction::Run(CliOptions {
+                command_lines: command_lines.iter().map(|x| x.to_string()).collect(),
+                headless,
+                log_beats,
+                ..CliOptions::default()
+            }))
+        };
+        assert_eq!(parse(&[]), run(&[], false, false));
+        assert_eq!(
+            parse(&[
+                "--start",
+                "--bpm",
+                "120",
+                "--pitch=587,440",
+                "--preset",
+                "waltz"
+            ]),
+            run(
+                &["preset waltz", "bpm 120", "pitch 587 440", "start"],
+                false,
+                false
+            )
+        );
+        assert_eq!(
+            parse(&["play", "--duration", "16 bars", "--log-beats", "--silent"]),
+            run(&["silent on", "timer 16 bars", "start"], true, true)
+        );
+        assert_eq!(
+            parse(&["--script=-", "--socket=/tmp/m.sock", "--tcp", "7777"]),
+            Ok(CliAction::Run(CliOptions {
+                script: Some("-".to_string()),
+                socket: Some("/tmp/m.sock".into()),
+                tcp_port: Some(7777),
+                ..CliOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["--osc=8000", "--osc-send", "9000", "--midi-clock", "follow"]),
+            Ok(CliAction::Run(CliOptions {
+                osc_port: Some(8000),
+                osc_send: Some("9000".to_string()),
+                midi_clock: Some(MidiClockMode::Follow),
+                ..CliOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["--sync", "follow", "--sync-port=47000"]),
+            Ok(CliAction::Run(CliOptions {
+                sync: Some(SyncMode::Follow),
+                sync_port: Some(47000),
+                ..CliOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["--sync", "boss"]),
+            Err("Unknown sync role \"boss\"".to_string())
+        );
+        assert_eq!(
+            parse(&["--osc-send", "9000"]),
+            Err("Option \"--osc-send\" needs \"--osc\"".to_string())
+        );
+        assert_eq!(parse(&["--bpm", "1", "-h"]), Ok(CliAction::Help));
+        assert_eq!(
+            parse(&["--bpm"]),
+            Err("Option \"--bpm\" needs a value".to_string())
+        );
+        assert_eq!(
+            parse(&["--tempo=90"]),
+            Err("Unknown option \"--tempo\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    settingsfile::{format_settings, read_settings, Entry, Section},
+    statusline::StatusTemplate,
+    xdg::{config_dir, state_dir},
+};
+
+/// Prompt of the REPL when the configuration does not set one
+pub const DEFAULT_PROMPT: &str = "♩♩♩♩: ";
+
+/// Lines of the input history that are kept when the configuration does not set a size
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Settings that are loaded at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub prompt: String,
+    /// Template of the status line, `None` for the default one
+    pub statusline: Option<StatusTemplate>,
+    /// Lines that the history file keeps, 0 disables the history file
+    pub history_size: usize,
+    /// Name or part of the name of the audio output device
+    pub device: Option<String>,
+    /// Defaults of the beat player, applied with `BeatPlayer::apply_settings`
+    pub player: Vec<Entry>,
+    /// Key names with the command lines that they execute
+    pub keys: Vec<Entry>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prompt: DEFAULT_PROMPT.to_string(),
+            statusline: None,
+            history_size: DEFAULT_HISTORY_SIZE,
+            device: None,
+            player: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Configuration file with the sections `[repl]`, `[audio]`, `[player]` and `[keys]`
+    ///
+    /// A missing file results in the default configuration.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut config = Config::default();
+        let error = |err: String| format!("{}: {}", path.display(), err);
+        for section in read_settings(path)? {
+            match section.name.as_str() {
+                "repl" | "audio" => {
+                    for entry in section.entries {
+                        match (section.name.as_str(), entry.key.as_str()) {
+                            ("repl", "prompt") => config.prompt = entry.value,
+                            ("repl", "statusline") if entry.value.is_empty() => {
+                                config.statusline = None
+                            }
+                            ("repl", "statusline") => {
+                                config.statusline = Some(
+                                    StatusTemplate::parse(&entry.value)
+                                        .map_err(|err| error(entry.invalid(&err)))?,
+                                )
+                            }
+                            ("repl", "history_size") => {
+                                config.history_size = entry
+                                    .value
+                                    .parse()
+                                    .map_err(|_| error(entry.invalid("not a number of lines")))?
+                            }
+                            ("audio", "device") if entry.value.is_empty() => config.device = None,
+                            ("audio", "device") => config.device = Some(entry.value),
+                            _ => return Err(error(entry.invalid("unknown setting"))),
+                        }
+                    }
+                }
+                "player" => config.player = section.entries,
+                "keys" => config.keys = section.entries,
+                "" => {
+                    return Err(error(format!(
+                        "Line {}: settings must be in a section",
+                        section.entries[0].line
+                    )))
+                }
+                x => return Err(error(format!("Unknown section \"{}\"", x))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Content of a configuration file that `load` reads back
+    pub fn to_file_content(&self) -> String {
+        let sections = [
+            Section {
+                name: "repl".to_string(),
+                entries: vec![
+                    Entry::new("prompt", self.prompt.clone()),
+                    Entry::new(
+                        "statusline",
+                        self.statusline
+                            .as_ref()
+                            .map(|x| x.to_string())
+                            .unwrap_or_default(),
+                    ),
+                    Entry::new("history_size", self.history_size.to_string()),
+                ],
+            },
+            Section {
+                name: "audio".to_string(),
+                entries: vec![Entry::new(
+                    "device",
+                    self.device.clone().unwrap_or_default(),
+                )],
+            },
+            Section {
+                name: "player".to_string(),
+                entries: self.player.clone(),
+            },
+            Section {
+                name: "keys".to_string(),
+                entries: self.keys.clone(),
+            },
+        ];
+        format!(
+            "# mnomer configuration, loaded at startup\n\
+            # [repl] statusline is a status line template, see \"help statusline\", empty for the default\n\
+            # [repl] history_size is the number of kept input lines, 0 disables the history file\n\
+            # [audio] device selects the first output device whose name contains it\n\
+            # [keys] binds F1 to F12, PageUp, PageDown, Home, End and Insert to command lines\n\n{}",
+            format_settings(&sections)
+        )
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mnomer/config.ini`
+pub fn config_file() -> Result<PathBuf, String> {
+    Ok(config_dir()?.join("config.ini"))
+}
+
+/// `$XDG_STATE_HOME/mnomer/history`
+pub fn history_file() -> Result<PathBuf, String> {
+    Ok(state_dir()?.join("history"))
+}
+
+#[cfg(test)]
+mod test_config {
+    use super::*;
+    use crate::settingsfile::write_file;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn test_load_and_write() {
+        let dir = TestDir::new();
+        let path = dir.join("config.ini");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        let config = Config {
+            device: Some("USB".to_string()),
+            statusline: Some(StatusTemplate::parse("<bold>{bpm}</bold> {pattern}").unwrap()),
+            history_size: 50,
+            player: vec![Entry::new("bpm", "120".to_string())],
+            keys: vec![Entry::new("F1", "preset waltz".to_string())],
+            ..Config::default()
+        };
+        write_file(&path, &config.to_file_content()).unwrap();
+        let loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded.prompt, DEFAULT_PROMPT);
+        assert_eq!(loaded.device, config.device);
+        assert_eq!(loaded.statusline, config.statusline);
+        assert_eq!(loaded.history_size, 50);
+        assert_eq!(loaded.player[0].value, "120");
+        assert_eq!(loaded.keys[0].key, "F1");
+
+        write_file(&path, "[repl]\ncolor = red\n").unwrap();
+        assert!(Config::load(&path)
+            .unwrap_err()
+            .ends_with("Line 2: Invalid color \"red\": unknown setting"));
+        write_file(&path, "[repl]\nstatusline = {tempo}\n").unwrap();
+        assert!(Config::load(&path)
+            .unwrap_err()
+            .contains("Line 2: Invalid statusline \"{tempo}\": Unknown placeholder"));
+    }
+}
@@ -0,0 +1,234 @@
+use cpal::{traits::StreamTrait, FromSample, Sample, Stream};
+
+use crate::{
+    audiooutput::{create_cpal_stream, output_device, AudioRenderer},
+    audiosignal::{AudioSignal, ToneConfiguration},
+};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Number of samples of the wave table that holds one period of the drone's waveform
+const WAVE_TABLE_SIZE: usize = 4096;
+/// Time in which the drone fades in and out and follows volume changes
+const RAMP_TIME: f64 = 0.05;
+
+/// Sustained reference tone or chord for intonation practice
+///
+/// The drone uses its own output stream, so it plays alone or mixed under the beat playback.
+pub struct Drone {
+    /// Frequencies of the voices of the drone
+    pub frequencies: Vec<f64>,
+    /// Volume in [0; 1]
+    pub volume: f64,
+    stream: Option<Stream>,
+    /// Volume that the audio callback approaches, as bits of a f32
+    target_gain: Arc<AtomicU32>,
+}
+
+impl Default for Drone {
+    fn default() -> Self {
+        Drone {
+            frequencies: Vec::new(),
+            volume: 0.5,
+            stream: None,
+            target_gain: Arc::new(AtomicU32::new(0f32.to_bits())),
+        }
+    }
+}
+
+impl Drone {
+    pub fn new() -> Drone {
+        Drone::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Start the drone with the given voices on `device`, `overtones` determines the timbre
+    ///
+    /// A running drone is stopped before.
+    pub fn start(
+        &mut self,
+        frequencies: Vec<f64>,
+        overtones: u8,
+        device: Option<&str>,
+    ) -> Result<(), String> {
+        if frequencies.is_empty() {
+            return Err("No drone pitch given".to_string());
+        }
+        for &frequency in &frequencies {
+            if !(20.0..=20000.0).contains(&frequency) {
+                return Err(format!("Value {} out of range", frequency));
+            }
+        }
+        self.stop();
+
+        let (device, config) = output_device(device)?;
+        let sample_rate = config.sample_rate().0 as f64;
+        let renderer = DroneRenderer::new(
+            &frequencies,
+            overtones,
+            sample_rate,
+            config.channels() as usize,
+            self.target_gain.clone(),
+        );
+        self.target_gain
+            .store((self.volume as f32).to_bits(), Ordering::Relaxed);
+        let stream = create_cpal_stream(device, config, renderer)?;
+        if stream.play().is_err() {
+            return Err("Something went wrong with drone playback".into());
+        }
+        self.stream = Some(stream);
+        self.frequencies = frequencies;
+        Ok(())
+    }
+
+    /// Fade out and stop the drone
+    pub fn stop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            self.target_gain.store(0f32.to_bits(), Ordering::Relaxed);
+            thread::sleep(Duration::from_secs_f64(RAMP_TIME * 1.5));
+            stream.pause().expect("Error during pause");
+        }
+    }
+
+    /// Set the volume in [0; 1], a running drone follows without clicks
+    pub fn set_volume(&mut self, volume: f64) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(format!("Volume {} out of range", volume));
+        }
+        self.volume = volume;
+        if self.is_playing() {
+            self.target_gain
+                .store((volume as f32).to_bits(), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// One period of the waveform, generated like the beats
+fn wave_table(overtones: u8) -> Vec<f32> {
+    // a frequency of 1Hz at a sample rate of the table size results in exactly one period
+    let mut table = AudioSignal::generate_tone(&ToneConfiguration {
+        frequency: 1.0,
+        sample_rate: WAVE_TABLE_SIZE as f64,
+        length: 1.0,
+        overtones,
+        channels: 1,
+    });
+    let peak = table
+        .signal
+        .iter()
+        .fold(0f32, |peak, sample| peak.max(sample.abs()));
+    if peak > 0.0 {
+        table *= 0.75 / peak as f64;
+    }
+    table.signal
+}
+
+/// Wave table oscillators for the voices of the drone
+struct DroneRenderer {
+    table: Vec<f32>,
+    phases: Vec<f64>,
+    increments: Vec<f64>,
+    gain: f32,
+    gain_step: f32,
+    target_gain: Arc<AtomicU32>,
+    channels: usize,
+}
+
+impl DroneRenderer {
+    fn new(
+        frequencies: &[f64],
+        overtones: u8,
+        sample_rate: f64,
+        channels: usize,
+        target_gain: Arc<AtomicU32>,
+    ) -> DroneRenderer {
+        DroneRenderer {
+            table: wave_table(overtones),
+            phases: vec![0.0; frequencies.len()],
+            increments: frequencies
+                .iter()
+                .map(|frequency| frequency * WAVE_TABLE_SIZE as f64 / sample_rate)
+                .collect(),
+            gain: 0.0,
+            gain_step: (1.0 / (RAMP_TIME * sample_rate)) as f32,
+            target_gain,
+            channels,
+        }
+    }
+}
+
+impl AudioRenderer for DroneRenderer {
+    fn render<T: Sample + FromSample<f32>>(&mut self, data: &mut [T]) {
+        let target_gain = f32::from_bits(self.target_gain.load(Ordering::Relaxed));
+        let voices = self.phases.len() as f32;
+        for output_frame in data.chunks_mut(self.channels) {
+            // ramp to the target volume to avoid clicks
+            if self.gain < target_gain {
+                self.gain = (self.gain + self.gain_step).min(target_gain);
+            } else if self.gain > target_gain {
+                self.gain = (self.gain - self.gain_step).max(target_gain);
+            }
+
+            let mut value = 0.0;
+            for (phase, increment) in self.phases.iter_mut().zip(&self.increments) {
+                let index = *phase as usize;
+                let fraction = (*phase - index as f64) as f32;
+                let next = self.table[(index + 1) % WAVE_TABLE_SIZE];
+                value += self.table[index] + (next - self.table[index]) * fraction;
+                *phase = (*phase + increment) % WAVE_TABLE_SIZE as f64;
+            }
+            output_frame.fill(T::from_sample(value / voices * self.gain));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_drone {
+    use super::*;
+
+    #[test]
+    fn test_wave_table() {
+        let table = wave_table(0);
+        assert_eq!(table.len(), WAVE_TABLE_SIZE);
+        let peak = table.iter().fold(0f32, |peak, x| peak.max(x.abs()));
+        assert!((peak - 0.75).abs() < 1e-6);
+        // one period of a pure sine without overtones
+        assert!(table[0].abs() < 1e-3);
+        assert!(table[WAVE_TABLE_SIZE / 4] > 0.7);
+        assert!(table[WAVE_TABLE_SIZE * 3 / 4] < -0.7);
+    }
+
+    #[test]
+    fn test_renderer_ramps() {
+        let target_gain = Arc::new(AtomicU32::new(0.5f32.to_bits()));
+        let sample_rate = 48000.0;
+        let mut renderer =
+            DroneRenderer::new(&[440.0, 660.0], 1, sample_rate, 2, target_gain.clone());
+        let ramp_frames = (RAMP_TIME * sample_rate) as usize;
+        let mut data = vec![0f32; 4 * ramp_frames];
+        renderer.render(&mut data);
+        // the channels of a frame are equal and the volume fades in
+        assert!(data.chunks(2).all(|frame| frame[0] == frame[1]));
+        let peak = |samples: &[f32]| samples.iter().fold(0f32, |peak, x| peak.max(x.abs()));
+        assert!(peak(&data[..ramp_frames / 5]) < 0.1);
+        assert!(peak(&data[2 * ramp_frames..]) <= 0.5 * 0.75 + 1e-6);
+        assert!(peak(&data[2 * ramp_frames..]) > 0.2);
+        assert_eq!(renderer.gain, 0.5);
+
+        // fading out reaches silence within the ramp time
+        target_gain.store(0f32.to_bits(), Ordering::Relaxed);
+        renderer.render(&mut data);
+        assert_eq!(renderer.gain, 0.0);
+        assert!(data[2 * ramp_frames..].iter().all(|x| *x == 0.0));
+    }
+}
@@ -1,7 +1,59 @@
+mod audiooutput;
 mod audiosignal;
 mod beatplayer;
+mod beatrenderer;
+mod cli;
+mod config;
+mod drone;
+mod midiclock;
+mod midifile;
+mod netsync;
+mod osc;
+mod pitch;
+mod playbackclock;
+mod presets;
+mod remotecontrol;
 mod repl;
+mod sessiontimer;
+mod settingsfile;
+mod snapshots;
+mod softwareclock;
+mod statusline;
+mod stoppablethread;
+mod taptempo;
+mod tempomap;
+#[cfg(test)]
+mod testdir;
+mod tuning;
+mod visual;
+mod xdg;
 
+pub use audiooutput::output_device_names;
 pub use audiosignal::{frequency_relative_semitone_equal_temperament, ToneConfiguration};
-pub use beatplayer::{BeatPattern, BeatPatternType, BeatPlayer};
-pub use repl::repl::{BuiltInOverwriteError, Repl};
+pub use beatplayer::{BeatPattern, BeatPatternType, BeatPlayer, PlayedBeat};
+pub use cli::{parse_args, CliAction, CliOptions, MidiClockMode, SyncMode, USAGE};
+pub use config::{config_file, history_file, Config, DEFAULT_HISTORY_SIZE, DEFAULT_PROMPT};
+pub use drone::Drone;
+pub use midiclock::{
+    follow_clock, open_virtual_ports, ClockSender, MidiReceiver, MidiTransport, PulseGrid,
+    SharedTransport, PULSES_PER_QUARTER,
+};
+pub use midifile::{
+    click_track, export_click_track, parse_tempo_map, read_tempo_map, ClickNotes, MAX_EXPORT_BARS,
+};
+pub use netsync::{
+    SharedTimeline, SyncFollower, SyncLeader, Timeline, DEFAULT_SYNC_PORT, SYNC_GROUP,
+};
+pub use osc::{OscArg, OscMessage, OscServer};
+pub use pitch::{note_name, note_names, parse_note, PitchSpec, DEFAULT_A4};
+pub use presets::{find_preset, Preset, PRESETS};
+pub use remotecontrol::{default_socket_path, RemoteControl};
+pub use repl::repl::{complete_path, BuiltInOverwriteError, RemoteChannel, RemoteCommand, Repl};
+pub use sessiontimer::{SessionTimer, TimerLimit};
+pub use settingsfile::{Entry, Section};
+pub use snapshots::{list_snapshots, load_snapshot, save_snapshot, snapshot_file};
+pub use statusline::{StatusTemplate, PLACEHOLDERS, STYLES};
+pub use taptempo::TapTempo;
+pub use tempomap::{TempoMap, TempoSection, MAX_TEMPO_MAP_BARS};
+pub use tuning::{parse_root, KeyboardMapping, Tuning};
+pub use visual::visual_lines;
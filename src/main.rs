@@ -1,14 +1,79 @@
+mod audiooutput;
 mod audiosignal;
 mod beatplayer;
+mod beatrenderer;
+mod cli;
+mod config;
+mod drone;
+mod midiclock;
+mod midifile;
+mod netsync;
+mod osc;
+mod pitch;
+mod playbackclock;
+mod presets;
+mod remotecontrol;
 mod repl;
+mod sessiontimer;
+mod settingsfile;
+mod snapshots;
+mod softwareclock;
+mod statusline;
+mod stoppablethread;
+mod taptempo;
+mod tempomap;
+#[cfg(test)]
+mod testdir;
+mod tuning;
+mod visual;
+mod xdg;
 
+use audiooutput::output_device_names;
 use audiosignal::{frequency_relative_semitone_equal_temperament, ToneConfiguration};
-use beatplayer::{BeatPattern, BeatPatternType, BeatPlayer};
-use repl::repl::{BuiltInOverwriteError, Repl};
+use beatplayer::{format_bpm, BeatPattern, BeatPatternType, BeatPlayer, BASE_BEAT_VALUE};
+use cli::{parse_args, CliAction, MidiClockMode, SyncMode, USAGE};
+use config::{config_file, history_file, Config};
+use midiclock::{follow_clock, open_virtual_ports};
+use midifile::{export_click_track, read_tempo_map, ClickNotes, MAX_EXPORT_BARS};
+use netsync::{SharedTimeline, SyncFollower, SyncLeader, DEFAULT_SYNC_PORT};
+use osc::OscServer;
+use pitch::{note_names, PitchSpec};
+use presets::{find_preset, PRESETS};
+use remotecontrol::RemoteControl;
+use repl::repl::{complete_path, Repl};
+use sessiontimer::SessionTimer;
+use settingsfile::write_file;
+use snapshots::{list_snapshots, load_snapshot, save_snapshot, snapshot_file};
+use statusline::{StatusTemplate, PLACEHOLDERS, STYLES};
 use std::convert::TryFrom;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use taptempo::TapTempo;
+use tuning::{parse_root, Tuning};
+use visual::visual_lines;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(CliAction::Run(options)) => options,
+        Ok(CliAction::Help) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Ok(CliAction::Version) => {
+            println!("mnomer {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
     // Create the tone configurations for the beatplayer
     let freq = 440.0;
     let normal_beat = ToneConfiguration {
@@ -25,9 +90,19 @@ fn main() -> Result<(), Box<dyn Error>> {
         ..normal_beat
     };
 
+    // defaults from the configuration file
+    let (config_name, config) = match config_file() {
+        Ok(path) => (path.display().to_string(), Config::load(&path)?),
+        Err(err) => {
+            eprintln!("Warning: {}, using the default configuration", err);
+            (String::from("default configuration"), Config::default())
+        }
+    };
+    let config_error = |err: String| format!("{}: {}", config_name, err);
+
     // beatplayer takes care of generating the beat and its playback
-    let beatplayer = BeatPlayer::new(
-        100,
+    let mut beatplayer = BeatPlayer::new(
+        100.0,
         4,
         normal_beat,
         accentuated_beat,
@@ -38,18 +113,122 @@ fn main() -> Result<(), Box<dyn Error>> {
             BeatPatternType::Beat,
         ]),
     );
+    beatplayer
+        .apply_settings(&config.player)
+        .map_err(config_error)?;
+    beatplayer.device.clone_from(&config.device);
+    beatplayer.status_template.clone_from(&config.statusline);
+
+    // MIDI clock synchronization on a virtual MIDI port
+    let mut midi_ports = match options.midi_clock {
+        Some(_) => Some(open_virtual_ports("mnomer")?),
+        None => None,
+    };
+    if let (Some(MidiClockMode::Send), Some(ports)) = (options.midi_clock, midi_ports.take()) {
+        beatplayer.set_midi_clock(Some(Arc::new(Mutex::new(ports))))?;
+    }
+
+    // tempo and beat sync with other instances on the local network or a MIDI clock
+    let timeline = SharedTimeline::default();
+    if options.sync.is_some() || options.midi_clock == Some(MidiClockMode::Follow) {
+        beatplayer.set_network_sync(Some(timeline.clone()));
+    }
 
     // create the user interface, the Read Evaluate Print Loop (REPL)
-    let mut repl = Repl::new(beatplayer, "♩♩♩♩: ".to_string());
+    let mut repl = Repl::new(beatplayer, config.prompt.clone());
+    for binding in &config.keys {
+        repl.bind_key(&binding.key, binding.value.clone())
+            .map_err(|err| config_error(binding.invalid(&err)))?;
+    }
+
+    add_repl_commands(&mut repl, &config)?;
+
+    // command line options are applied like the REPL commands
+    for command_line in &options.command_lines {
+        if let Err(err) = repl.execute(command_line) {
+            eprintln!("{}", err.replace("\n\r", "\n"));
+            std::process::exit(2);
+        }
+    }
+
+    // remote commands are executed like the entered ones
+    let _remote_control = if options.socket.is_some() || options.tcp_port.is_some() {
+        let (remote_control, channel) =
+            RemoteControl::start(options.socket.clone(), options.tcp_port)?;
+        repl.add_remote(channel);
+        Some(remote_control)
+    } else {
+        None
+    };
+    let _osc_server = match options.osc_port {
+        Some(port) => {
+            let (osc_server, channel) = OscServer::start(port, options.osc_send.as_deref())?;
+            repl.add_remote(channel);
+            Some(osc_server)
+        }
+        None => None,
+    };
+    // the ports of a followed clock have to stay open
+    if let Some(ports) = midi_ports.as_mut() {
+        repl.add_remote(follow_clock(ports.as_mut(), timeline.clone())?);
+    }
+    let sync_port = options.sync_port.unwrap_or(DEFAULT_SYNC_PORT);
+    let (_sync_leader, _sync_follower) = match options.sync {
+        Some(SyncMode::Lead) => (Some(SyncLeader::start(sync_port, timeline)?), None),
+        // the leader's changes are executed like remote commands
+        Some(SyncMode::Follow) => {
+            let (follower, channel) = SyncFollower::start(sync_port, timeline)?;
+            repl.add_remote(channel);
+            (None, Some(follower))
+        }
+        None => (None, None),
+    };
 
-    add_repl_commands(&mut repl)?;
+    // piped commands are executed as script
+    let headless = options.headless;
+    let log_beats = options.log_beats;
+    let script = match options.script {
+        None if !headless && !io::stdin().is_terminal() => Some("-".to_string()),
+        script => script,
+    };
 
-    repl.run()?;
+    if headless || script.is_some() {
+        let stop = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, stop.clone())?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, stop.clone())?;
+        if let Some(script) = script {
+            let result = if script == "-" {
+                repl.run_script(io::stdin().lock(), &stop, log_beats)
+            } else {
+                match File::open(&script) {
+                    Ok(file) => repl.run_script(BufReader::new(file), &stop, log_beats),
+                    Err(err) => Err(format!("Could not open script: {}", err)),
+                }
+            };
+            if let Err(err) = result {
+                let name = if script == "-" { "stdin" } else { &script };
+                eprintln!("{}: {}", name, err);
+                std::process::exit(1);
+            }
+        }
+        // play until SIGINT, SIGTERM or the end of the session timer
+        if headless {
+            repl.run_headless(&stop, log_beats)?;
+        }
+    } else {
+        if config.history_size > 0 {
+            match history_file() {
+                Ok(path) => repl.load_history(path, config.history_size)?,
+                Err(err) => eprintln!("Warning: {}, the history is not saved", err),
+            }
+        }
+        repl.run()?;
+    }
 
     Ok(())
 }
 
-fn add_repl_commands(repl: &mut Repl<BeatPlayer>) -> Result<(), BuiltInOverwriteError> {
+fn add_repl_commands(repl: &mut Repl<BeatPlayer>, config: &Config) -> Result<(), Box<dyn Error>> {
     repl.set_command(
         // ENTER to toggle playback
         "".to_string(),
@@ -96,12 +275,12 @@ fn add_repl_commands(repl: &mut Repl<BeatPlayer>) -> Result<(), BuiltInOverwrite
     repl.set_command(
         "bpm".to_string(),
         Box::new(|args, bp: &mut BeatPlayer| match args {
-            Some(bpm_str) => match bpm_str.parse::<u16>() {
+            Some(bpm_str) => match bpm_str.parse::<f64>() {
                 Ok(bpm) => {
                     if !bp.set_bpm(bpm) {
-                        return Err(format!("Could not set bpm value of {}", bpm));
+                        return Err(format!("Could not set bpm value of {}", bpm_str));
                     }
-                    Ok(format!("Bpm set to {}", bpm))
+                    Ok(format!("Bpm set to {}", format_bpm(bp.bpm)))
                 }
                 Err(_) => Err(format!("Could not parse \"{}\" to a value", bpm_str)),
             },
@@ -109,7 +288,7 @@ fn add_repl_commands(repl: &mut Repl<BeatPlayer>) -> Result<(), BuiltInOverwrite
         }),
         Some(format!(
             "{}\n  {}",
-            "\"bpm <value>\" where <value> >= 1",
+            "\"bpm <value>\" where <value> > 0, fractional values like 93.5 are allowed",
             "This value is based on a beat value of 4 (1/4 note value)"
         )),
     )?;
@@ -125,36 +304,570 @@ fn add_repl_commands(repl: &mut Repl<BeatPlayer>) -> Result<(), BuiltInOverwrite
             None => Err("No pattern found".to_string()),
         }),
         Some(format!(
-            "{}\n  {}\n  {}",
+            "{}\n  {}\n  {}\n  {}\n  {}",
             "\"pattern <pattern>\"",
-            "<pattern> must be in the form of `[!|+|.]*`",
-            "`!` = accentuated beat  `+` = normal beat  `.` = pause"
+            "<pattern> consists of the beats `!` = accentuated beat  `+` = normal beat  `.` = pause",
+            "`|` separates bars that may differ in length, e.g. `!+++|!++`",
+            "`(...)xN` repeats a group N times, e.g. `(!++)x2 !+`",
+            "whitespace is ignored and `#` starts a comment"
+        )),
+    )?;
+
+    repl.set_command(
+        "preset".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            None | Some("list") => {
+                let presets: Vec<String> = PRESETS
+                    .iter()
+                    .map(|preset| {
+                        format!(
+                            "{:<10} {:<18} 1/{:<3} {:>4}bpm  {}",
+                            preset.name,
+                            preset.pattern,
+                            preset.beat_value,
+                            format_bpm(preset.bpm),
+                            preset.description
+                        )
+                    })
+                    .collect();
+                Ok(presets.join("\n\r"))
+            }
+            Some(name) => {
+                let preset = find_preset(name)
+                    .ok_or_else(|| format!("Unknown preset \"{}\", see \"preset list\"", name))?;
+                let pattern = BeatPattern::try_from(preset.pattern)?;
+                bp.set_rhythm(&pattern, preset.beat_value, preset.bpm)?;
+                Ok(format!(
+                    "Preset {} set: pattern {}, value 1/{}, bpm {}",
+                    preset.name,
+                    pattern,
+                    preset.beat_value,
+                    format_bpm(preset.bpm)
+                ))
+            }
+        }),
+        Some(format!(
+            "{}\n  {}",
+            "\"preset <name>\" sets pattern, value and suggested bpm of a common pattern or meter",
+            "\"preset list\" shows all presets"
+        )),
+    )?;
+
+    repl.set_command(
+        "save".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args {
+            Some(name) => {
+                save_snapshot(&snapshot_file()?, &name, bp.settings())?;
+                Ok(format!("Saved snapshot \"{}\"", name))
+            }
+            None => Err("No snapshot name supplied".to_string()),
+        }),
+        Some(
+            "\"save <name>\" stores bpm, value, pattern, voices and tuning as snapshot <name>, not the drone"
+                .to_string(),
+        ),
+    )?;
+
+    repl.set_command(
+        "load".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args {
+            Some(name) => {
+                bp.apply_settings(&load_snapshot(&snapshot_file()?, &name)?)?;
+                Ok(format!("Loaded snapshot \"{}\"", name))
+            }
+            None => Err("No snapshot name supplied".to_string()),
+        }),
+        Some("\"load <name>\" restores the settings of snapshot <name>".to_string()),
+    )?;
+
+    repl.set_command(
+        "list".to_string(),
+        Box::new(|_, _: &mut BeatPlayer| {
+            let path = snapshot_file()?;
+            let names = list_snapshots(&path)?;
+            if names.is_empty() {
+                Ok(format!("No snapshots saved in {}", path.display()))
+            } else {
+                Ok(format!("Saved snapshots: {}", names.join(", ")))
+            }
+        }),
+        Some("\"list\" shows the names of the saved snapshots".to_string()),
+    )?;
+
+    repl.set_command(
+        "device".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            None => Ok(format!(
+                "Audio device is {}",
+                bp.device.as_deref().unwrap_or("the default device")
+            )),
+            Some("default") => {
+                bp.set_device(None)?;
+                Ok("Audio device set to the default device".to_string())
+            }
+            Some(name) => {
+                bp.set_device(Some(name.to_string()))?;
+                Ok(format!("Audio device set to \"{}\"", name))
+            }
+        }),
+        Some(format!(
+            "{}\n  {}",
+            "\"device <name>\" selects the first audio output device whose name contains <name>",
+            "\"device default\" selects the default output device"
+        )),
+    )?;
+
+    repl.set_command(
+        "silent".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            Some("on") => {
+                bp.set_silent(true)?;
+                Ok("Playing silently".to_string())
+            }
+            Some("off") => {
+                bp.set_silent(false)?;
+                Ok("Playing on the audio device".to_string())
+            }
+            _ => Err("Expected \"on\" or \"off\"".to_string()),
+        }),
+        Some(format!(
+            "{}\n  {}",
+            "\"silent <on|off>\" plays without audio, a software clock drives the visual view,",
+            "the terminal bell and the events, also used when there is no audio device"
+        )),
+    )?;
+
+    repl.set_command(
+        "bell".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            Some("on") => {
+                bp.set_bell(true);
+                Ok("Ringing the terminal bell at every beat".to_string())
+            }
+            Some("off") => {
+                bp.set_bell(false);
+                Ok("Terminal bell off".to_string())
+            }
+            None => Ok(format!(
+                "Terminal bell is {}",
+                if bp.bell() { "on" } else { "off" }
+            )),
+            _ => Err("Expected \"on\" or \"off\"".to_string()),
+        }),
+        Some(
+            "\"bell <on|off>\" rings the terminal bell at every beat that is not a pause"
+                .to_string(),
+        ),
+    )?;
+
+    let startup_config = config.clone();
+    repl.set_command(
+        "config".to_string(),
+        Box::new(move |args, bp: &mut BeatPlayer| {
+            let args = args.unwrap_or_default();
+            let mut words = args.split_whitespace();
+            match words.next() {
+                None => Ok(format!(
+                    "Configuration file is {}",
+                    config_file()?.display()
+                )),
+                Some("write") => {
+                    let path = match words.next() {
+                        Some(path) => path.into(),
+                        None => config_file()?,
+                    };
+                    if path.exists() {
+                        return Err(format!("\"{}\" already exists", path.display()));
+                    }
+                    let config = Config {
+                        device: bp.device.clone(),
+                        statusline: bp.status_template.clone(),
+                        player: bp.settings(),
+                        ..startup_config.clone()
+                    };
+                    write_file(&path, &config.to_file_content())?;
+                    Ok(format!("Configuration written to {}", path.display()))
+                }
+                Some(x) => Err(format!("Unknown argument \"{}\"", x)),
+            }
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}",
+            "\"config\" shows the path of the configuration file that is loaded at startup",
+            "\"config write [path]\" writes the current settings as a starting configuration file",
+            "existing files are not overwritten"
+        )),
+    )?;
+
+    repl.set_command(
+        "statusline".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            None => Ok(match &bp.status_template {
+                Some(template) => format!("Status line template: {}", template),
+                None => "Default status line".to_string(),
+            }),
+            Some("default") => {
+                bp.status_template = None;
+                Ok("Default status line".to_string())
+            }
+            Some(template) => {
+                bp.status_template = Some(StatusTemplate::parse(template)?);
+                Ok("Status line template set".to_string())
+            }
+        }),
+        Some(format!(
+            "{}\n  placeholders: {{{}}}\n  styles: <{}> until </name>\n  {}",
+            "\"statusline [<template>|default]\" shows or sets the template of the status line",
+            PLACEHOLDERS.join("} {"),
+            STYLES.join("> <"),
+            "e.g. statusline <bold>{bpm}</bold> bpm  {pattern}  <cyan>{elapsed}</cyan>"
+        )),
+    )?;
+
+    repl.set_command(
+        "export-midi".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| {
+            let args = args.unwrap_or_default();
+            let args: Vec<&str> = args.split_whitespace().collect();
+            let (path, bars, notes) = match args[..] {
+                [path, bars] => (path, bars, ClickNotes::default()),
+                [path, bars, accent, beat] => {
+                    let note = |value: &str| match value.parse::<u8>() {
+                        Ok(note) if note <= 127 => Ok(note),
+                        _ => Err(format!("\"{}\" is not a MIDI note number", value)),
+                    };
+                    let notes = ClickNotes {
+                        accent: note(accent)?,
+                        beat: note(beat)?,
+                    };
+                    (path, bars, notes)
+                }
+                _ => return Err("Expected \"export-midi <file.mid> <bars>\"".to_string()),
+            };
+            let bars = match bars.parse::<u64>() {
+                Ok(bars) if (1..=MAX_EXPORT_BARS).contains(&bars) => bars,
+                _ => {
+                    return Err(format!(
+                        "Bars must be a number from 1 to {}",
+                        MAX_EXPORT_BARS
+                    ))
+                }
+            };
+            export_click_track(
+                Path::new(path),
+                &bp.beat_pattern,
+                bp.beat_value,
+                bp.bpm,
+                bars,
+                notes,
+            )?;
+            Ok(format!("Exported {} bars to \"{}\"", bars, path))
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}",
+            "\"export-midi <file.mid> <bars> [<accent note> <beat note>]\"",
+            "writes the click as Standard MIDI File with tempo and time signatures",
+            "The notes are General MIDI percussion notes on channel 10, by default 76 and 77"
+        )),
+    )?;
+
+    repl.set_command(
+        "import-midi".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            None => match bp.tempo_map() {
+                Some(map) => {
+                    let sections: Vec<String> = map
+                        .sections
+                        .iter()
+                        .enumerate()
+                        .map(|(index, section)| format!("{:>3}: {}", index + 1, section))
+                        .collect();
+                    Ok(format!(
+                        "Tempo map \"{}\":\n\r{}",
+                        map.name,
+                        sections.join("\n\r")
+                    ))
+                }
+                None => Ok("No tempo map, use \"import-midi <file.mid>\"".to_string()),
+            },
+            Some("off") => {
+                bp.set_tempo_map(None)?;
+                Ok("Following bpm, value and pattern again".to_string())
+            }
+            Some(path) => {
+                let map = read_tempo_map(Path::new(path))?;
+                let message = format!(
+                    "Imported {} bars in {} sections from \"{}\"",
+                    map.bars(),
+                    map.sections.len(),
+                    path
+                );
+                bp.set_tempo_map(Some(map))?;
+                Ok(message)
+            }
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}",
+            "\"import-midi <file.mid>\" plays the tempo changes and time signatures of a MIDI file",
+            "The playback follows its sections bar by bar until bpm, value or pattern are set",
+            "\"import-midi\" shows the sections, \"import-midi off\" leaves the tempo map"
         )),
     )?;
 
     repl.set_command(
         "pitch".to_string(),
         Box::new(|args, bp: &mut BeatPlayer| {
-            let pitches: Vec<f64> = match args {
+            let pitches = match args {
                 Some(pitches) => pitches
-                    .split(' ')
-                    .filter_map(|x| x.parse::<f64>().ok())
-                    .collect(),
-                None => return Err("No pattern found".to_string()),
+                    .split_whitespace()
+                    .map(PitchSpec::try_from)
+                    .collect::<Result<Vec<PitchSpec>, String>>()?,
+                None => return Err("No pitches supplied".to_string()),
             };
             if pitches.len() != 2 {
                 return Err("Wrong number of pitches".to_string());
             };
-            bp.set_pitches(pitches[0], pitches[1])?;
-            Ok(format!("Pitch set to {} and {}", pitches[0], pitches[1]))
+            // relative pitches are based on the current normal beat pitch
+            let reference = bp.beat.frequency;
+            let accent_pitch = pitches[0].frequency(reference, &bp.tuning, bp.a4)?;
+            let normal_pitch = pitches[1].frequency(reference, &bp.tuning, bp.a4)?;
+            bp.set_pitches(accent_pitch, normal_pitch)?;
+            Ok(format!(
+                "Pitch set to {:.3}Hz and {:.3}Hz",
+                accent_pitch, normal_pitch
+            ))
         }),
         Some(format!(
-            "{}\n  {}",
+            "{}\n  {}\n  {}\n  {}\n  {}",
             "\"pitch <accentuated beat pitch> <normal beat pitch>\"",
+            "a pitch is a frequency like `440`, a note name like `A4`, `F#3` or `Bb5`",
+            "or scale steps relative to the current normal beat pitch like `+5`, `-2` or `0`",
+            "a step is a degree of the tuning's scale, a semitone in equal temperament",
             "pitches must should within [20; 20k]Hz"
         )),
     )?;
 
+    repl.set_command(
+        "reference".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args {
+            Some(a4_str) => match PitchSpec::try_from(a4_str.as_str()) {
+                Ok(PitchSpec::Frequency(a4)) => {
+                    bp.set_a4(a4)?;
+                    Ok(format!("Reference pitch set to A4 = {}Hz", a4))
+                }
+                _ => Err(format!("Could not parse \"{}\" to a frequency", a4_str)),
+            },
+            None => Ok(format!("Reference pitch is A4 = {}Hz", bp.a4)),
+        }),
+        Some(format!(
+            "{}\n  {}",
+            "\"reference <frequency>\" sets the pitch of A4 for note names, e.g. 440 or 442",
+            "must be within [400; 480]Hz"
+        )),
+    )?;
+
+    repl.set_command(
+        "drone".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| {
+            let args = match args {
+                Some(args) => args,
+                None if bp.drone.is_playing() => {
+                    return Ok(format!("Drone plays {:.3?}Hz", bp.drone.frequencies))
+                }
+                None => return Err("No drone pitch supplied".to_string()),
+            };
+            let mut words = args.split_whitespace();
+            match words.next() {
+                Some("off") => {
+                    bp.drone.stop();
+                    Ok("Drone stopped".to_string())
+                }
+                Some("volume") => match words.next().map(|x| x.parse::<f64>()) {
+                    Some(Ok(volume)) => {
+                        bp.drone.set_volume(volume / 100.0)?;
+                        Ok(format!("Drone volume set to {}%", volume))
+                    }
+                    _ => Err("No volume in percent supplied".to_string()),
+                },
+                Some(root) => {
+                    // relative pitches are based on the current normal beat pitch
+                    let reference = bp.beat.frequency;
+                    let root = PitchSpec::try_from(root)?;
+                    let mut frequencies = vec![root.frequency(reference, &bp.tuning, bp.a4)?];
+                    for interval in words {
+                        match interval.parse::<f64>() {
+                            Ok(steps) => frequencies.push(
+                                root.interval_frequency(steps, reference, &bp.tuning, bp.a4)?,
+                            ),
+                            Err(_) => {
+                                return Err(format!(
+                                    "Could not parse \"{}\" to an interval",
+                                    interval
+                                ))
+                            }
+                        }
+                    }
+                    bp.drone
+                        .start(frequencies, bp.beat.overtones, bp.device.as_deref())?;
+                    Ok(format!("Drone plays {:.3?}Hz", bp.drone.frequencies))
+                }
+                None => Err("No drone pitch supplied".to_string()),
+            }
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}\n  {}",
+            "\"drone <pitch> [interval...]\", \"drone volume <percent>\" or \"drone off\"",
+            "plays a sustained reference tone, alone or under the beat",
+            "<pitch> is a frequency, a note name or scale steps relative to the normal beat pitch",
+            "[interval...] adds voices in scale steps above the pitch, e.g. `drone D3 7 12`"
+        )),
+    )?;
+
+    repl.set_command(
+        "tuning".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| {
+            let args = match args {
+                Some(args) => args,
+                None => return Ok(format!("Tuning is {}", bp.tuning)),
+            };
+            let mut words = args.split_whitespace();
+            let root = |word: Option<&str>, default: i32| match word {
+                Some(word) => {
+                    parse_root(word).ok_or_else(|| format!("\"{}\" is not a root note", word))
+                }
+                None => Ok(default),
+            };
+            bp.tuning = match words.next() {
+                Some("root") => match words.next() {
+                    Some(note) => Tuning {
+                        root: root(Some(note), 0)?,
+                        ..bp.tuning.clone()
+                    },
+                    None => return Err("No root note supplied".to_string()),
+                },
+                Some("load") => match (words.next(), words.next()) {
+                    (Some(scl), kbm) => {
+                        Tuning::load(scl.as_ref(), kbm.map(|x| x.as_ref()), bp.tuning.root)?
+                    }
+                    (None, _) => return Err("No Scala file supplied".to_string()),
+                },
+                Some(temperament) => {
+                    Tuning::temperament(temperament, root(words.next(), bp.tuning.root)?)?
+                }
+                None => return Ok(format!("Tuning is {}", bp.tuning)),
+            };
+            Ok(format!("Tuning set to {}", bp.tuning))
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}\n  {}",
+            "\"tuning <equal|just|pythagorean|meantone> [root]\", \"tuning root <note>\"",
+            "or \"tuning load <file.scl> [file.kbm]\" to import a Scala scale and keyboard mapping",
+            "note names, relative pitches and drone intervals resolve through the tuning",
+            "[root] is the note of the first scale degree like `D` or `Bb3`, default is C"
+        )),
+    )?;
+
+    repl.set_command(
+        "notenames".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args.as_deref() {
+            Some("on") => {
+                bp.show_note_names = true;
+                Ok("Showing note names".to_string())
+            }
+            Some("off") => {
+                bp.show_note_names = false;
+                Ok("Hiding note names".to_string())
+            }
+            _ => Err("Expected \"on\" or \"off\"".to_string()),
+        }),
+        Some(
+            "\"notenames <on|off>\" shows note names next to the pitches in the status line"
+                .to_string(),
+        ),
+    )?;
+
+    repl.set_command(
+        "reset".to_string(),
+        Box::new(|_, bp: &mut BeatPlayer| {
+            bp.reset_counters();
+            Ok("Bar counter and elapsed time reset".to_string())
+        }),
+        Some("Reset the bar counter, the position and the elapsed playing time".to_string()),
+    )?;
+
+    repl.set_command(
+        "timer".to_string(),
+        Box::new(|args, bp: &mut BeatPlayer| match args {
+            Some(timer_str) if timer_str == "off" => {
+                bp.set_timer(None);
+                Ok("Timer removed".to_string())
+            }
+            Some(timer_str) => {
+                let timer = SessionTimer::try_from(timer_str.as_str())?;
+                let msg = format!("Timer set to {}", timer);
+                bp.set_timer(Some(timer));
+                Ok(msg)
+            }
+            None => match bp.timer_remaining() {
+                Some(remaining) => Ok(format!("Timer stops playback in {}", remaining)),
+                None => Err("No timer set".to_string()),
+            },
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}\n  {}",
+            "\"timer <duration> [fade <duration>] [chime]\" or \"timer off\"",
+            "<duration> is playing time like `90`, `45s`, `5m`, `1h30m`, `2:30` or bars like `16 bars`",
+            "fade: fade out the playback before the end  chime: play a chime after the end",
+            "Without arguments the remaining time is shown"
+        )),
+    )?;
+
+    let mut tap_tempo = TapTempo::new();
+    repl.set_key_mode(
+        "tap".to_string(),
+        "tap (t or SPACE, ESC to leave): ".to_string(),
+        Box::new(move |key, bp: &mut BeatPlayer| match key {
+            't' | ' ' => match tap_tempo.tap(Instant::now()) {
+                Some(beats_per_minute) => {
+                    // taps are beats of the pattern, bpm is based on the beat value 1/4
+                    let bpm = beats_per_minute * BASE_BEAT_VALUE as f64 / bp.beat_value as f64;
+                    let bpm = (bpm * 10.0).round() / 10.0;
+                    if !tap_tempo.should_apply(bpm, bp.bpm, bp.is_playing()) {
+                        return Ok(format!(
+                            "{} bpm, playing {} bpm",
+                            format_bpm(bpm),
+                            format_bpm(bp.bpm)
+                        ));
+                    }
+                    if !bp.set_bpm(bpm) {
+                        return Err(format!("Could not set bpm value of {}", format_bpm(bpm)));
+                    }
+                    Ok(format!("{} bpm", format_bpm(bpm)))
+                }
+                None => Ok("keep tapping".to_string()),
+            },
+            _ => Err("Press t or SPACE to tap".to_string()),
+        }),
+        Some(format!(
+            "{}\n  {}\n  {}",
+            "\"tap\" enters the tap tempo mode",
+            "Tap the beats with t or SPACE to set the bpm, leave with ESC or ENTER",
+            "a running playback follows after 4 taps when the tempo differs by 1 bpm or more"
+        )),
+    )?;
+
+    repl.set_view(
+        "visual".to_string(),
+        Box::new(|bp: &mut BeatPlayer, columns, rows| {
+            visual_lines(bp.played_beat().as_ref(), bp.bpm, columns, rows)
+        }),
+        Some(format!(
+            "{}\n  {}",
+            "\"visual\" shows the full-screen visual metronome with a flashing beat indicator",
+            "SPACE starts and stops the playback, leave with ESC, ENTER or q"
+        )),
+    )?;
+
     repl.set_command(
         "value".to_string(),
         Box::new(|args, bp: &mut BeatPlayer| match args {
@@ -176,5 +889,108 @@ fn add_repl_commands(repl: &mut Repl<BeatPlayer>) -> Result<(), BuiltInOverwrite
         )),
     )?;
 
+    add_completions(repl)?;
+    Ok(())
+}
+
+/// Tab completion of the command arguments
+fn add_completions(repl: &mut Repl<BeatPlayer>) -> Result<(), String> {
+    fn words(candidates: &[&str]) -> Vec<String> {
+        candidates.iter().map(|x| x.to_string()).collect()
+    }
+    for (name, candidates) in [
+        ("silent", ["on", "off"].as_slice()),
+        ("bell", &["on", "off"]),
+        ("notenames", &["on", "off"]),
+        ("statusline", &["default"]),
+        ("timer", &["off"]),
+    ] {
+        repl.set_completion(
+            name,
+            Box::new(move |previous, _, _| match previous {
+                [] => words(candidates),
+                _ => Vec::new(),
+            }),
+        )?;
+    }
+    repl.set_completion(
+        "preset",
+        Box::new(|previous, _, _| match previous {
+            [] => PRESETS
+                .iter()
+                .map(|x| x.name.to_string())
+                .chain(["list".to_string()])
+                .collect(),
+            _ => Vec::new(),
+        }),
+    )?;
+    for name in ["save", "load"] {
+        repl.set_completion(
+            name,
+            Box::new(|previous, _, _| match (previous, snapshot_file()) {
+                ([], Ok(path)) => list_snapshots(&path).unwrap_or_default(),
+                _ => Vec::new(),
+            }),
+        )?;
+    }
+    repl.set_completion(
+        "device",
+        Box::new(|previous, _, _| match previous {
+            [] => output_device_names()
+                .unwrap_or_default()
+                .into_iter()
+                .chain(["default".to_string()])
+                .collect(),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "config",
+        Box::new(|previous, word, _| match previous {
+            [] => words(&["write"]),
+            ["write"] => complete_path(word),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "export-midi",
+        Box::new(|previous, word, _| match previous {
+            [] => complete_path(word),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "import-midi",
+        Box::new(|previous, word, _| match previous {
+            [] => [complete_path(word), words(&["off"])].concat(),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "pitch",
+        Box::new(|previous, _, _| match previous {
+            [] | [_] => note_names(),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "drone",
+        Box::new(|previous, _, _| match previous {
+            [] => [note_names(), words(&["off", "volume"])].concat(),
+            _ => Vec::new(),
+        }),
+    )?;
+    repl.set_completion(
+        "tuning",
+        Box::new(|previous, word, _| match previous {
+            [] => [Tuning::TEMPERAMENTS.as_slice(), &["root", "load"]]
+                .concat()
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            ["load"] | ["load", _] => complete_path(word),
+            _ => Vec::new(),
+        }),
+    )?;
     Ok(())
 }
@@ -0,0 +1,531 @@
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering, mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    beatplayer::BeatPattern,
+    netsync::{micros, AnchorEstimator, SharedTimeline, PHASE_TOLERANCE},
+    playbackclock::{BeatGrid, PlaybackClock},
+    repl::repl::{RemoteChannel, RemoteCommand},
+    settingsfile::Entry,
+    stoppablethread::{StoppableThread, POLL_INTERVAL},
+};
+
+/// MIDI clock pulses per quarter note
+pub const PULSES_PER_QUARTER: u64 = 24;
+
+/// MIDI clock pulses per sixteenth note, the unit of the song position
+const PULSES_PER_SIXTEENTH: u64 = PULSES_PER_QUARTER / 4;
+
+/// Largest song position in sixteenth notes
+const MAX_SONG_POSITION: u64 = 0x3fff;
+
+const CLOCK: u8 = 0xf8;
+const START: u8 = 0xfa;
+const CONTINUE: u8 = 0xfb;
+const STOP: u8 = 0xfc;
+const SONG_POSITION: u8 = 0xf2;
+
+/// Connection to other MIDI devices, e.g. ALSA sequencer ports or an in-process loopback
+pub trait MidiTransport: Send {
+    fn send(&mut self, message: &[u8]) -> Result<(), String>;
+
+    /// Call `receiver` with every received message, it replaces the previous receiver
+    fn set_receiver(&mut self, receiver: MidiReceiver) -> Result<(), String>;
+}
+
+/// Callback for received MIDI messages
+pub type MidiReceiver = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Transport that the clock thread and the player share
+pub type SharedTransport = Arc<Mutex<Box<dyn MidiTransport>>>;
+
+/// Receiver of a loopback transport
+#[cfg(test)]
+type LoopbackReceiver = Arc<Mutex<Option<MidiReceiver>>>;
+
+/// In-process transport whose messages are received by its peer
+#[cfg(test)]
+pub struct Loopback {
+    own: LoopbackReceiver,
+    peer: LoopbackReceiver,
+}
+
+#[cfg(test)]
+impl Loopback {
+    /// Two transports that are connected to each other
+    pub fn pair() -> (Loopback, Loopback) {
+        let (a, b): (LoopbackReceiver, LoopbackReceiver) = Default::default();
+        (
+            Loopback {
+                own: a.clone(),
+                peer: b.clone(),
+            },
+            Loopback { own: b, peer: a },
+        )
+    }
+}
+
+#[cfg(test)]
+impl MidiTransport for Loopback {
+    fn send(&mut self, message: &[u8]) -> Result<(), String> {
+        if let Some(receiver) = self.peer.lock().unwrap().as_mut() {
+            receiver(message);
+        }
+        Ok(())
+    }
+
+    fn set_receiver(&mut self, receiver: MidiReceiver) -> Result<(), String> {
+        *self.own.lock().unwrap() = Some(receiver);
+        Ok(())
+    }
+}
+
+/// Virtual ALSA sequencer ports that other applications and devices connect to
+#[cfg(unix)]
+pub struct VirtualPorts {
+    name: String,
+    output: midir::MidiOutputConnection,
+    input: Option<midir::MidiInputConnection<()>>,
+}
+
+#[cfg(unix)]
+impl VirtualPorts {
+    pub fn open(name: &str) -> Result<VirtualPorts, String> {
+        use midir::os::unix::VirtualOutput;
+        let output = midir::MidiOutput::new(name)
+            .map_err(|err| format!("Could not open MIDI output: {}", err))?
+            .create_virtual(name)
+            .map_err(|err| format!("Could not create MIDI port \"{}\": {}", name, err))?;
+        Ok(VirtualPorts {
+            name: name.to_string(),
+            output,
+            input: None,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl MidiTransport for VirtualPorts {
+    fn send(&mut self, message: &[u8]) -> Result<(), String> {
+        self.output
+            .send(message)
+            .map_err(|err| format!("Could not send MIDI message: {}", err))
+    }
+
+    fn set_receiver(&mut self, mut receiver: MidiReceiver) -> Result<(), String> {
+        use midir::os::unix::VirtualInput;
+        // the previous port has to be closed before a port with the same name is created
+        self.input = None;
+        let input = midir::MidiInput::new(&self.name)
+            .map_err(|err| format!("Could not open MIDI input: {}", err))?
+            .create_virtual(&self.name, move |_, message, _| receiver(message), ())
+            .map_err(|err| format!("Could not create MIDI port \"{}\": {}", self.name, err))?;
+        self.input = Some(input);
+        Ok(())
+    }
+}
+
+/// Open the virtual ports of the platform
+pub fn open_virtual_ports(name: &str) -> Result<Box<dyn MidiTransport>, String> {
+    #[cfg(unix)]
+    return Ok(Box::new(VirtualPorts::open(name)?));
+    #[cfg(not(unix))]
+    Err(format!(
+        "Virtual MIDI ports like \"{}\" are not supported on this platform",
+        name
+    ))
+}
+
+/// Frames of the MIDI clock pulses of a beat grid
+///
+/// A pulse between two beats is placed proportionally between their onsets, so the pulses follow
+/// the beats without drift, also when a beat is shorter than a pulse. Positions are counted in
+/// units of a whole note that divide every pulse and beat.
+#[derive(Debug, Clone)]
+pub struct PulseGrid {
+    beats: BeatGrid,
+    /// First position, first beat and units per beat of each section
+    sections: Vec<(u128, u64, u128)>,
+    /// Units and beats of the cycle of sections, `None` for a constant beat value
+    cycle: Option<(u128, u64)>,
+    units_per_pulse: u128,
+}
+
+impl PulseGrid {
+    /// Pulses of a beat grid with beats of 1/`beat_value`
+    pub fn constant(beats: BeatGrid, beat_value: u16) -> PulseGrid {
+        // a single section that is not repeated
+        PulseGrid {
+            cycle: None,
+            ..PulseGrid::cycle(beats, &[(1, beat_value)]).expect("The beat value is zero")
+        }
+    }
+
+    /// Pulses of a beat grid whose cycle has sections with a number of beats of 1/beat value
+    pub fn cycle(beats: BeatGrid, sections: &[(u64, u16)]) -> Result<PulseGrid, String> {
+        let units = sections
+            .iter()
+            .try_fold(PULSES_PER_QUARTER as u128 * 4, |units, (_, beat_value)| {
+                lcm(units, *beat_value as u128).filter(|units| *units <= u64::MAX as u128)
+            })
+            .ok_or("The beat values have no common grid of MIDI clock pulses")?;
+        let mut grid = Vec::new();
+        let (mut position, mut beat) = (0, 0);
+        for &(count, beat_value) in sections.iter().filter(|(count, _)| *count > 0) {
+            let units_per_beat = units / beat_value as u128;
+            grid.push((position, beat, units_per_beat));
+            position += count as u128 * units_per_beat;
+            beat += count;
+        }
+        if beat == 0 {
+            return Err("A cycle of sections needs at least one beat".to_string());
+        }
+        Ok(PulseGrid {
+            beats,
+            sections: grid,
+            cycle: Some((position, beat)),
+            units_per_pulse: units / (PULSES_PER_QUARTER as u128 * 4),
+        })
+    }
+
+    /// First frame of the pulse with index `pulse`
+    pub fn onset(&self, pulse: u64) -> u64 {
+        let position = pulse as u128 * self.units_per_pulse;
+        let (cycle_beat, position) = match self.cycle {
+            Some((units, beats)) => ((position / units) as u64 * beats, position % units),
+            None => (0, position),
+        };
+        let index = self
+            .sections
+            .partition_point(|section| section.0 <= position)
+            - 1;
+        let (first_position, first_beat, units_per_beat) = self.sections[index];
+        let beat = cycle_beat + first_beat + ((position - first_position) / units_per_beat) as u64;
+        let fraction = (position - first_position) % units_per_beat;
+        let onset = self.beats.onset(beat);
+        if fraction == 0 {
+            return onset;
+        }
+        let length = (self.beats.onset(beat + 1) - onset) as u128;
+        onset + ((length * fraction * 2 + units_per_beat) / (units_per_beat * 2)) as u64
+    }
+}
+
+/// Least common multiple, `None` if it is zero or does not fit
+fn lcm(a: u128, b: u128) -> Option<u128> {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    match x {
+        0 => None,
+        gcd => (a / gcd).checked_mul(b).filter(|lcm| *lcm > 0),
+    }
+}
+
+/// Sends MIDI clock, start, stop and song position for a playback run
+///
+/// The pulses follow the frames of the audio clock, which are extrapolated between the audio
+/// callbacks. Dropping the sender sends stop.
+pub struct ClockSender {
+    thread: StoppableThread,
+    transport: SharedTransport,
+}
+
+impl ClockSender {
+    /// Start sending at frame 0, which is at `song_position` sixteenth notes of the song
+    ///
+    /// Song position 0 is sent with start, others with continue, so that the receivers do not
+    /// jump back to the start of the song.
+    pub fn start(
+        transport: SharedTransport,
+        clock: PlaybackClock,
+        pulses: PulseGrid,
+        sample_rate: f64,
+        song_position: u64,
+    ) -> ClockSender {
+        let thread = {
+            let transport = transport.clone();
+            StoppableThread::spawn(move |stop| {
+                let send = |message: &[u8]| {
+                    // a missing receiver must not stop the metronome
+                    let _ = transport.lock().unwrap().send(message);
+                };
+                let song_position = song_position.min(MAX_SONG_POSITION);
+                send(&[
+                    SONG_POSITION,
+                    (song_position & 0x7f) as u8,
+                    (song_position >> 7) as u8,
+                ]);
+                send(&[if song_position == 0 { START } else { CONTINUE }]);
+                let mut pulse = 0;
+                let mut last_frames = (clock.frames(), Instant::now());
+                while !stop.load(Ordering::Relaxed) {
+                    let frames = clock.frames();
+                    if frames != last_frames.0 {
+                        last_frames = (frames, Instant::now());
+                    }
+                    // at most one buffer of 50ms ahead of the audio callback
+                    let ahead =
+                        (last_frames.1.elapsed().as_secs_f64().min(0.05) * sample_rate) as u64;
+                    let frame = (frames + ahead).min(clock.end_frame());
+                    while pulses.onset(pulse) <= frame && pulses.onset(pulse) < clock.end_frame() {
+                        send(&[CLOCK]);
+                        pulse += 1;
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })
+        };
+        ClockSender { thread, transport }
+    }
+}
+
+impl Drop for ClockSender {
+    fn drop(&mut self) {
+        self.thread.stop();
+        let _ = self.transport.lock().unwrap().send(&[STOP]);
+    }
+}
+
+/// Tempo measurement, song position and transport of an external MIDI clock
+#[derive(Debug, Default)]
+struct ClockFollower {
+    last_pulse: Option<Instant>,
+    /// Intervals of the last quarter note
+    intervals: VecDeque<Duration>,
+    /// Bpm that was set last
+    bpm: Option<f64>,
+    /// Song position of the next pulse in pulses
+    position: u64,
+    /// Song position of the last pulse, `None` before the first pulse after start
+    pulse: Option<u64>,
+}
+
+impl ClockFollower {
+    /// Smallest tempo difference that changes the bpm, smaller ones are jitter
+    const BPM_TOLERANCE: f64 = 0.5;
+
+    /// REPL command line for a received message at `time`
+    fn receive(&mut self, message: &[u8], time: Instant) -> Option<String> {
+        match *message.first()? {
+            START | CONTINUE => {
+                if message[0] == START {
+                    self.position = 0;
+                }
+                self.last_pulse = None;
+                self.intervals.clear();
+                self.pulse = None;
+                Some("start".to_string())
+            }
+            STOP => Some("stop".to_string()),
+            SONG_POSITION => {
+                let sixteenths = *message.get(1)? as u64 | (*message.get(2)? as u64) << 7;
+                self.position = sixteenths * PULSES_PER_SIXTEENTH;
+                None
+            }
+            CLOCK => {
+                self.pulse = Some(self.position);
+                self.position += 1;
+                if let Some(last_pulse) = self.last_pulse.replace(time) {
+                    self.intervals.push_back(time - last_pulse);
+                }
+                if self.intervals.len() < PULSES_PER_QUARTER as usize {
+                    return None;
+                }
+                let quarter: Duration = self.intervals.drain(..).sum();
+                let bpm = (600.0 / quarter.as_secs_f64()).round() / 10.0;
+                match self.bpm {
+                    Some(last) if (bpm - last).abs() < Self::BPM_TOLERANCE => None,
+                    _ => {
+                        self.bpm = Some(bpm);
+                        Some(format!("bpm {}", bpm))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Microseconds by which a playback that played frame 0 at `anchor` is behind the pulse at song
+/// position `pulse` that was received at `time`
+///
+/// The phases are compared within the cycle of the pattern of `settings`, so that the accents
+/// follow the song position. `None` if the settings have no bpm, value or pattern.
+fn pulse_lag(pulse: u64, time: i64, anchor: i64, settings: &[Entry]) -> Option<i64> {
+    let setting = |key| {
+        settings
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+    };
+    let bpm: f64 = setting("bpm")?.parse().ok()?;
+    let beat_value: u16 = setting("value")?.parse().ok()?;
+    let beats = BeatPattern::try_from(setting("pattern")?)
+        .ok()?
+        .pattern
+        .len();
+    let quarter = 60e6 / bpm;
+    let cycle = beats as f64 * quarter * 4.0 / beat_value as f64;
+    let song_time = pulse as f64 * quarter / PULSES_PER_QUARTER as f64;
+    let lag = (song_time - (time - anchor) as f64).rem_euclid(cycle);
+    let lag = if lag > cycle / 2.0 { lag - cycle } else { lag };
+    Some(lag.round() as i64)
+}
+
+/// Follow the clock, start, continue, stop and song position messages that `transport` receives
+///
+/// The messages become the REPL commands `start`, `stop` and `bpm` with the tempo of the last
+/// quarter note, whose results are shown by the REPL. The clock of the playback run of
+/// `timeline` is shifted to the song position of the pulses when they differ by more than 5 ms.
+pub fn follow_clock(
+    transport: &mut dyn MidiTransport,
+    timeline: SharedTimeline,
+) -> Result<RemoteChannel, String> {
+    let (command_sender, commands) = mpsc::channel();
+    let (events, _) = mpsc::channel();
+    let mut follower = ClockFollower::default();
+    let mut estimator = AnchorEstimator::default();
+    let epoch = Instant::now();
+    transport.set_receiver(Box::new(move |message| {
+        let now = micros(epoch);
+        if let Some(command_line) = follower.receive(message, Instant::now()) {
+            // nobody waits for the reply
+            let (reply, _) = mpsc::channel();
+            let _ = command_sender.send(RemoteCommand {
+                command_line,
+                reply,
+            });
+        }
+        if message.first() != Some(&CLOCK) {
+            return;
+        }
+        let timeline = timeline.lock().unwrap().clone();
+        let anchor = estimator.update(timeline.as_ref(), now);
+        if let (Some(timeline), Some(anchor), Some(pulse)) = (timeline, anchor, follower.pulse) {
+            let lag = pulse_lag(pulse, now, anchor, &timeline.settings);
+            if let Some(lag) = lag.filter(|lag| lag.abs() > PHASE_TOLERANCE) {
+                let frames = lag as f64 * timeline.sample_rate / 1e6;
+                timeline.clock.shift(frames.round() as i64);
+                estimator.jumped(lag);
+            }
+        }
+    }))?;
+    Ok(RemoteChannel { commands, events })
+}
+
+#[cfg(test)]
+mod test_midiclock {
+    use super::*;
+
+    #[test]
+    fn test_clock_sender_and_follower() {
+        let (master, mut slave) = Loopback::pair();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let messages = received.clone();
+        slave
+            .set_receiver(Box::new(move |message| {
+                messages.lock().unwrap().push(message.to_vec())
+            }))
+            .unwrap();
+
+        // 25 pulses until the end at frame 2400
+        let clock = PlaybackClock::new();
+        clock.set_end(2401, 0);
+        clock.advance(10_000);
+        let transport: SharedTransport = Arc::new(Mutex::new(Box::new(master)));
+        let pulses = PulseGrid::constant(BeatGrid::constant(2400.0), 4);
+        let sender =
+            ClockSender::start(transport.clone(), clock.clone(), pulses.clone(), 48000.0, 0);
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        let mut messages = std::mem::take(&mut *received.lock().unwrap());
+        assert_eq!(messages[0], vec![SONG_POSITION, 0, 0]);
+        assert_eq!(messages[1], vec![START]);
+        assert_eq!(messages[2..27], vec![vec![CLOCK]; 25]);
+        assert_eq!(messages[27..], vec![vec![STOP]]);
+
+        // a restart continues at the song position of bar 51 of 4/4
+        let sender = ClockSender::start(transport, clock, pulses, 48000.0, 200);
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        messages = std::mem::take(&mut *received.lock().unwrap());
+        assert_eq!(messages[0], vec![SONG_POSITION, 0x48, 1]);
+        assert_eq!(messages[1], vec![CONTINUE]);
+
+        let mut follower = ClockFollower::default();
+        let start = Instant::now();
+        assert_eq!(follower.receive(&[START], start), Some("start".to_string()));
+        // 120 bpm is one pulse every 500 / 24 ms
+        let pulse = |index: u32| start + Duration::from_micros(500_000 / 24) * index;
+        let commands: Vec<String> = (0..=48)
+            .filter_map(|index| follower.receive(&[CLOCK], pulse(index)))
+            .collect();
+        assert_eq!(commands, vec!["bpm 120".to_string()]);
+        assert_eq!(follower.pulse, Some(48));
+        assert_eq!(
+            follower.receive(&[STOP], pulse(49)),
+            Some("stop".to_string())
+        );
+
+        // continue at the song position of the second bar
+        assert_eq!(follower.receive(&[SONG_POSITION, 16, 0], pulse(50)), None);
+        assert_eq!(
+            follower.receive(&[CONTINUE], pulse(50)),
+            Some("start".to_string())
+        );
+        assert_eq!(follower.pulse, None);
+        follower.receive(&[CLOCK], pulse(51));
+        assert_eq!(follower.pulse, Some(96));
+    }
+
+    #[test]
+    fn test_pulse_grid() {
+        // 1/8 beats of 3000 frames have 12 pulses of 250 frames
+        let pulses = PulseGrid::constant(BeatGrid::constant(3000.0), 8);
+        assert_eq!(pulses.onset(12), 3000);
+        assert_eq!(pulses.onset(13), 3250);
+
+        // 1/12 beats of 2000 frames have 8 pulses
+        let pulses = PulseGrid::constant(BeatGrid::constant(2000.0), 12);
+        assert_eq!(pulses.onset(8), 2000);
+        assert_eq!(pulses.onset(5), 1250);
+
+        let beats = BeatGrid::cycle(&[(0, 1000.0)]);
+        assert!(beats.is_err());
+        let beats = BeatGrid::constant(1000.0);
+        assert!(PulseGrid::cycle(beats.clone(), &[(0, 4)]).is_err());
+        assert!(PulseGrid::cycle(beats, &[(1, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_pulse_lag() {
+        // 4 beats of 1/4 at 120 bpm are a cycle of 2 s
+        let settings = vec![
+            Entry::new("bpm", "120".to_string()),
+            Entry::new("value", "4".to_string()),
+            Entry::new("pattern", "!+++".to_string()),
+        ];
+        // pulse 24 is the second beat, 0.5 s after the start of the song
+        assert_eq!(pulse_lag(24, 1_500_000, 1_000_000, &settings), Some(0));
+        assert_eq!(pulse_lag(24, 1_500_000, 1_010_000, &settings), Some(10_000));
+        assert_eq!(pulse_lag(24, 1_500_000, 990_000, &settings), Some(-10_000));
+        // a whole cycle later is in phase, too
+        assert_eq!(pulse_lag(24 + 96, 1_500_000, 1_000_000, &settings), Some(0));
+        // a playback that is a beat behind jumps forward, three beats behind is one ahead
+        assert_eq!(
+            pulse_lag(48, 1_500_000, 1_000_000, &settings),
+            Some(500_000)
+        );
+        assert_eq!(
+            pulse_lag(96, 1_500_000, 1_000_000, &settings),
+            Some(-500_000)
+        );
+        assert_eq!(pulse_lag(24, 1_500_000, 1_000_000, &settings[..2]), None);
+    }
+}
@@ -0,0 +1,397 @@
+use std::path::Path;
+
+use crate::{
+    beatplayer::{BeatPattern, BeatPatternType, BASE_BEAT_VALUE},
+    tempomap::{TempoMap, TempoSection, MAX_TEMPO_MAP_BARS},
+};
+
+/// Ticks per quarter note, divisible by 3 and 5 so that triplets and quintuplets are exact
+const TICKS_PER_QUARTER: u64 = 960;
+
+/// Upper limit for the number of exported bars
+pub const MAX_EXPORT_BARS: u64 = 10_000;
+
+/// MIDI channel 10 (index 9) is the percussion channel of General MIDI
+const PERCUSSION_CHANNEL: u8 = 9;
+
+/// General MIDI percussion notes of the click
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClickNotes {
+    pub accent: u8,
+    pub beat: u8,
+}
+
+impl Default for ClickNotes {
+    /// Hi and low wood block
+    fn default() -> Self {
+        ClickNotes {
+            accent: 76,
+            beat: 77,
+        }
+    }
+}
+
+/// Type 0 Standard MIDI File with the click of `bars` bars of the pattern
+///
+/// Every bar whose length differs from the previous one gets a time signature meta event. Time
+/// signatures need a power of two as denominator, so bars with other beat values like 1/12 are
+/// written as the equivalent meter, e.g. 12 beats of 1/12 as 4/4, or without time signature.
+pub fn click_track(
+    pattern: &BeatPattern,
+    beat_value: u16,
+    bpm: f64,
+    bars: u64,
+    notes: ClickNotes,
+) -> Vec<u8> {
+    let ticks_per_whole = TICKS_PER_QUARTER * BASE_BEAT_VALUE as u64;
+    // rounded per beat instead of adding up a rounded duration, so that there is no drift
+    let beat_tick =
+        |beat: u64| (beat * ticks_per_whole + beat_value as u64 / 2) / beat_value as u64;
+    let note_length = (beat_tick(1) / 2).max(1);
+
+    let mut events: Vec<(u64, Vec<u8>)> = Vec::new();
+    // the tempo meta event has 3 bytes, so tempos below about 3.58 bpm are written as the slowest,
+    // and readers divide by it, so tempos above 120e6 bpm are written as the fastest
+    let tempo = (60_000_000.0 / bpm).round().clamp(1.0, 0xff_ffff as f64) as u32;
+    events.push((0, meta_event(0x51, &tempo.to_be_bytes()[1..])));
+    let mut last_length = None;
+    for bar in 0..bars {
+        let onset = pattern.bar_onset(bar);
+        let length = pattern.bar_onset(bar + 1) - onset;
+        if last_length != Some(length) {
+            if let Some((numerator, denominator)) = time_signature(length, beat_value) {
+                let exponent = denominator.trailing_zeros() as u8;
+                events.push((
+                    beat_tick(onset),
+                    meta_event(0x58, &[numerator, exponent, 24, 8]),
+                ));
+            }
+            last_length = Some(length);
+        }
+        for beat in onset..onset + length {
+            let (note, velocity) =
+                match pattern.pattern[(beat % pattern.pattern.len() as u64) as usize] {
+                    BeatPatternType::Accent => (notes.accent, 127),
+                    BeatPatternType::Beat => (notes.beat, 96),
+                    BeatPatternType::Pause => continue,
+                };
+            let tick = beat_tick(beat);
+            events.push((tick, vec![0x90 | PERCUSSION_CHANNEL, note, velocity]));
+            events.push((tick + note_length, vec![0x80 | PERCUSSION_CHANNEL, note, 0]));
+        }
+    }
+    let end = beat_tick(pattern.bar_onset(bars));
+    // note offs come before note ons and meta events of the same tick
+    events.sort_by_key(|(tick, event)| (*tick, event[0] != (0x80 | PERCUSSION_CHANNEL)));
+    events.push((end.max(events.last().unwrap().0), meta_event(0x2f, &[])));
+
+    let mut track = Vec::new();
+    let mut last_tick = 0;
+    for (tick, event) in events {
+        push_variable_length(&mut track, tick - last_tick);
+        track.extend(event);
+        last_tick = tick;
+    }
+
+    let mut file = Vec::new();
+    file.extend(b"MThd");
+    file.extend(6u32.to_be_bytes());
+    file.extend(0u16.to_be_bytes());
+    file.extend(1u16.to_be_bytes());
+    file.extend((TICKS_PER_QUARTER as u16).to_be_bytes());
+    file.extend(b"MTrk");
+    file.extend((track.len() as u32).to_be_bytes());
+    file.extend(track);
+    file
+}
+
+/// Write the click track to `path`
+pub fn export_click_track(
+    path: &Path,
+    pattern: &BeatPattern,
+    beat_value: u16,
+    bpm: f64,
+    bars: u64,
+    notes: ClickNotes,
+) -> Result<(), String> {
+    let content = click_track(pattern, beat_value, bpm, bars, notes);
+    std::fs::write(path, content)
+        .map_err(|err| format!("Could not write \"{}\": {}", path.display(), err))
+}
+
+/// Numerator and power of two denominator of a bar with `length` beats of 1/`beat_value`
+///
+/// Beat values with an odd factor are tuplets of the beat value without it, e.g. 1/12 are triplets
+/// of 1/4.
+fn time_signature(length: u64, beat_value: u16) -> Option<(u8, u16)> {
+    (beat_value.trailing_zeros()..7)
+        .map(|exponent| 1u16 << exponent)
+        .find(|denominator| (length * *denominator as u64).is_multiple_of(beat_value as u64))
+        .and_then(|denominator| {
+            let numerator = length * denominator as u64 / beat_value as u64;
+            Some((u8::try_from(numerator).ok()?, denominator))
+        })
+}
+
+/// Tempo map of a Standard MIDI File, see `parse_tempo_map`
+pub fn read_tempo_map(path: &Path) -> Result<TempoMap, String> {
+    let content = std::fs::read(path)
+        .map_err(|err| format!("Could not read \"{}\": {}", path.display(), err))?;
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    parse_tempo_map(&content, name).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Tempo map with one bar per bar of the file until its last event
+///
+/// Time signatures take effect at the next bar line. The tempo of each bar is its average tempo,
+/// so the bar lines stay in time with the file even if the tempo changes within a bar.
+pub fn parse_tempo_map(content: &[u8], name: String) -> Result<TempoMap, String> {
+    let mut reader = ByteReader {
+        bytes: content,
+        pos: 0,
+    };
+    if reader.bytes(4)? != b"MThd" {
+        return Err("Not a Standard MIDI File".to_string());
+    }
+    let header = reader.chunk()?;
+    if header.len() < 6 {
+        return Err("Invalid header".to_string());
+    }
+    let division = u16::from_be_bytes([header[4], header[5]]) as u64;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err("SMPTE time division is not supported".to_string());
+    }
+
+    let mut tempos: Vec<(u64, u64)> = Vec::new();
+    let mut time_signatures: Vec<(u64, u16, u16)> = Vec::new();
+    let mut end_tick = 0;
+    while reader.pos < content.len() {
+        let chunk_type = reader.bytes(4)?;
+        let chunk = reader.chunk()?;
+        if chunk_type != b"MTrk" {
+            continue;
+        }
+        let mut track = ByteReader {
+            bytes: chunk,
+            pos: 0,
+        };
+        let mut tick = 0;
+        let mut running_status = None;
+        while track.pos < chunk.len() {
+            tick += track.variable_length()?;
+            let mut status = track.bytes(1)?[0];
+            if status < 0x80 {
+                // running status, the byte belongs to the data
+                status = running_status.ok_or("Data byte without status")?;
+                track.pos -= 1;
+            }
+            match status {
+                0xff => {
+                    let meta_type = track.bytes(1)?[0];
+                    let length = track.variable_length()?;
+                    let data = track.bytes(length as usize)?;
+                    match (meta_type, data) {
+                        (0x51, [a, b, c]) => {
+                            let tempo = u32::from_be_bytes([0, *a, *b, *c]) as u64;
+                            tempos.push((tick, tempo.max(1)));
+                        }
+                        (0x58, [numerator, exponent, ..]) if *numerator > 0 && *exponent <= 8 => {
+                            time_signatures.push((tick, *numerator as u16, 1 << exponent));
+                        }
+                        _ => (),
+                    }
+                }
+                0xf0 | 0xf7 => {
+                    let length = track.variable_length()?;
+                    track.bytes(length as usize)?;
+                }
+                0x80..=0xef => {
+                    running_status = Some(status);
+                    let data_bytes = if matches!(status & 0xf0, 0xc0 | 0xd0) {
+                        1
+                    } else {
+                        2
+                    };
+                    track.bytes(data_bytes)?;
+                }
+                _ => return Err(format!("Invalid status byte {:#04x}", status)),
+            }
+        }
+        end_tick = end_tick.max(tick);
+    }
+    tempos.sort_by_key(|tempo| tempo.0);
+    time_signatures.sort_by_key(|time_signature| time_signature.0);
+
+    // microseconds from the start to `tick`
+    let time = |tick: u64| {
+        let mut time = 0.0;
+        let (mut last_tick, mut tempo) = (0, 500_000);
+        for &(change_tick, change_tempo) in tempos.iter().take_while(|x| x.0 < tick) {
+            time += (change_tick - last_tick) as f64 * tempo as f64 / division as f64;
+            (last_tick, tempo) = (change_tick, change_tempo);
+        }
+        time + (tick - last_tick) as f64 * tempo as f64 / division as f64
+    };
+
+    let mut sections = Vec::new();
+    let mut tick = 0;
+    while tick < end_tick {
+        if sections.len() as u64 >= MAX_TEMPO_MAP_BARS {
+            return Err(format!("More than {} bars", MAX_TEMPO_MAP_BARS));
+        }
+        let (_, beats, beat_value) = time_signatures
+            .iter()
+            .take_while(|x| x.0 <= tick)
+            .last()
+            .copied()
+            .unwrap_or((0, 4, 4));
+        let bar_ticks =
+            (beats as u64 * division * BASE_BEAT_VALUE as u64 / beat_value as u64).max(1);
+        let quarters = bar_ticks as f64 / division as f64;
+        let bpm = quarters * 60_000_000.0 / (time(tick + bar_ticks) - time(tick));
+        sections.push(TempoSection {
+            bars: 1,
+            beats,
+            beat_value,
+            bpm: (bpm * 1000.0).round() / 1000.0,
+        });
+        tick += bar_ticks;
+    }
+    TempoMap { name, sections }.normalize()
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(count))
+            .ok_or_else(|| "Unexpected end of file".to_string())?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    /// Content of a chunk after its type
+    fn chunk(&mut self) -> Result<&'a [u8], String> {
+        let length = u32::from_be_bytes(self.bytes(4)?.try_into().unwrap());
+        self.bytes(length as usize)
+    }
+
+    fn variable_length(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.bytes(1)?[0];
+            value = (value << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid variable length quantity".to_string())
+    }
+}
+
+fn meta_event(meta_type: u8, data: &[u8]) -> Vec<u8> {
+    let mut event = vec![0xff, meta_type];
+    push_variable_length(&mut event, data.len() as u64);
+    event.extend(data);
+    event
+}
+
+/// Append a variable length quantity, 7 bits per byte with the most significant first
+fn push_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod test_midifile {
+    use super::*;
+
+    #[test]
+    fn test_click_track() {
+        let pattern = BeatPattern::try_from("!+.").unwrap();
+        let file = click_track(&pattern, 4, 120.0, 2, ClickNotes::default());
+        assert_eq!(&file[..14], b"MThd\0\0\0\x06\0\0\0\x01\x03\xc0");
+        assert_eq!(&file[14..18], b"MTrk");
+        let track = &file[22..];
+        assert_eq!(
+            track.len() as u32,
+            u32::from_be_bytes(file[18..22].try_into().unwrap())
+        );
+        // 500000 microseconds per quarter and 3/4
+        assert_eq!(&track[..7], &[0, 0xff, 0x51, 3, 0x07, 0xa1, 0x20]);
+        assert_eq!(&track[7..14], &[0, 0xff, 0x58, 4, 3, 2, 24]);
+        // accent, its note off after half a beat and the normal beat
+        assert_eq!(&track[15..19], &[0, 0x99, 76, 127]);
+        assert_eq!(&track[19..24], &[0x83, 0x60, 0x89, 76, 0]);
+        assert_eq!(&track[24..28], &[0x83, 0x60, 0x99, 77]);
+        // end of track after 6 beats
+        assert_eq!(&track[track.len() - 5..], &[0x8b, 0x20, 0xff, 0x2f, 0]);
+
+        // the slowest tempo of the meta event is 16777215 microseconds per quarter
+        let tempo = |bpm| click_track(&pattern, 4, bpm, 1, ClickNotes::default())[22..29].to_vec();
+        assert_eq!(tempo(3.6), [0, 0xff, 0x51, 3, 0xfe, 0x50, 0x2b]);
+        assert_eq!(
+            tempo(60.0 / 16.777215),
+            [0, 0xff, 0x51, 3, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(tempo(1.0), [0, 0xff, 0x51, 3, 0xff, 0xff, 0xff]);
+        assert_eq!(tempo(1e9), [0, 0xff, 0x51, 3, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_tempo_map() {
+        // the exported click track has the tempo and meter of the pattern
+        let pattern = BeatPattern::try_from("!+++|!++").unwrap();
+        let file = click_track(&pattern, 4, 90.0, 4, ClickNotes::default());
+        let map = parse_tempo_map(&file, "click".to_string()).unwrap();
+        let section = |beats| TempoSection {
+            bars: 1,
+            beats,
+            beat_value: 4,
+            bpm: 90.0,
+        };
+        assert_eq!(
+            map.sections,
+            vec![section(4), section(3), section(4), section(3)]
+        );
+
+        // running status, a tempo change in the middle of the second bar and 6/8 in the third bar
+        let mut track = vec![0, 0xff, 0x58, 4, 4, 2, 24, 8];
+        track.extend([0, 0x99, 76, 100, 0x60, 76, 0]);
+        track.extend([0x83, 0x60, 0xff, 0x51, 3, 0x03, 0xd0, 0x90]);
+        track.extend([0x81, 0x40, 0xff, 0x58, 4, 6, 3, 24, 8]);
+        track.extend([0x82, 0x20, 0xff, 0x2f, 0]);
+        let mut file = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+        file.extend((track.len() as u32).to_be_bytes());
+        file.extend(track);
+        let map = parse_tempo_map(&file, "song".to_string()).unwrap();
+        let bpms: Vec<f64> = map.sections.iter().map(|section| section.bpm).collect();
+        assert_eq!(bpms, vec![120.0, 160.0, 240.0]);
+        assert_eq!(map.sections[2].beats, 6);
+        assert_eq!(map.sections[2].beat_value, 8);
+
+        assert!(parse_tempo_map(b"RIFF", String::new()).is_err());
+    }
+
+    #[test]
+    fn test_time_signature() {
+        assert_eq!(time_signature(7, 8), Some((7, 8)));
+        assert_eq!(time_signature(12, 12), Some((4, 4)));
+        assert_eq!(time_signature(3, 2), Some((3, 2)));
+        assert_eq!(time_signature(5, 3), None);
+        assert_eq!(time_signature(3, 12), Some((1, 4)));
+    }
+}
@@ -0,0 +1,612 @@
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{atomic::Ordering, mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    beatplayer::BeatPattern,
+    playbackclock::PlaybackClock,
+    repl::repl::{RemoteChannel, RemoteCommand},
+    settingsfile::Entry,
+    stoppablethread::{StoppableThread, POLL_INTERVAL},
+};
+
+/// Multicast group to which the leaders send their state
+pub const SYNC_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 47, 47);
+
+/// UDP port of the multicast group if none is given
+pub const DEFAULT_SYNC_PORT: u16 = 47447;
+
+const MESSAGE_PREFIX: &str = "mnomer-sync";
+
+/// Interval in which the leader sends its state when nothing changes
+const STATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval in which the follower measures the clock offset to the leader
+const PING_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time without state messages after which a follower accepts another leader
+const LEADER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Length of the windows in which the latest anchor estimate is looked for, in microseconds
+const ANCHOR_WINDOW: i64 = 250_000;
+
+/// Largest phase difference to the leader that a follower keeps, in microseconds
+pub const PHASE_TOLERANCE: i64 = 5_000;
+
+/// Number of offset measurements of which the least delayed one is used
+const OFFSET_SAMPLES: usize = 8;
+
+/// Playback run that is shared with the other instances
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub clock: PlaybackClock,
+    pub sample_rate: f64,
+    /// bpm, value and pattern of the run
+    pub settings: Vec<Entry>,
+}
+
+/// Current playback run of the player, `None` while it is stopped
+pub type SharedTimeline = Arc<Mutex<Option<Timeline>>>;
+
+/// Datagram of the sync protocol, all times are microseconds of the sender's clock
+///
+/// The datagrams are not authenticated, every host on the local network can send them.
+#[derive(Debug, Clone, PartialEq)]
+enum SyncMessage {
+    /// Leader time of frame 0 of the playback run, `None` while stopped, and its settings, which
+    /// are only bpm, value and pattern
+    State {
+        anchor: Option<i64>,
+        settings: Vec<Entry>,
+    },
+    /// Follower asks for the leader time, sent at `t1`
+    Ping { t1: i64 },
+    /// Leader received the ping at `t2` and answered at `t3`
+    Pong { t1: i64, t2: i64, t3: i64 },
+}
+
+impl SyncMessage {
+    fn encode(&self) -> String {
+        match self {
+            SyncMessage::State { anchor, settings } => {
+                let mut message = format!("{} state", MESSAGE_PREFIX);
+                match anchor {
+                    Some(anchor) => message += &format!(" {}", anchor),
+                    None => message += " -",
+                }
+                for entry in settings {
+                    message += &format!(" {}={}", entry.key, entry.value);
+                }
+                message
+            }
+            SyncMessage::Ping { t1 } => format!("{} ping {}", MESSAGE_PREFIX, t1),
+            SyncMessage::Pong { t1, t2, t3 } => {
+                format!("{} pong {} {} {}", MESSAGE_PREFIX, t1, t2, t3)
+            }
+        }
+    }
+
+    fn decode(datagram: &[u8]) -> Option<SyncMessage> {
+        let mut fields = std::str::from_utf8(datagram).ok()?.split_whitespace();
+        if fields.next()? != MESSAGE_PREFIX {
+            return None;
+        }
+        let kind = fields.next()?;
+        let mut times = fields.clone().map(|field| field.parse::<i64>().ok());
+        Some(match kind {
+            "state" => SyncMessage::State {
+                anchor: times.next()?,
+                settings: fields
+                    .skip(1)
+                    .map(|field| {
+                        let (key, value) = field.split_once('=')?;
+                        is_sync_setting(key, value).then(|| Entry::new(key, value.to_string()))
+                    })
+                    .collect::<Option<_>>()?,
+            },
+            "ping" => SyncMessage::Ping { t1: times.next()?? },
+            "pong" => SyncMessage::Pong {
+                t1: times.next()??,
+                t2: times.next()??,
+                t3: times.next()??,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a leader may share the setting, which the followers execute as REPL command
+fn is_sync_setting(key: &str, value: &str) -> bool {
+    match key {
+        "bpm" => value
+            .parse::<f64>()
+            .is_ok_and(|bpm| bpm.is_finite() && bpm > 0.0),
+        "value" => value.parse::<u16>().is_ok_and(|value| value > 0),
+        "pattern" => BeatPattern::try_from(value).is_ok(),
+        _ => false,
+    }
+}
+
+/// Microseconds since `epoch`
+pub fn micros(epoch: Instant) -> i64 {
+    epoch.elapsed().as_micros() as i64
+}
+
+/// Estimate of the time at which a playback run played frame 0
+///
+/// The audio callback advances the clock a buffer ahead of the playback, so the latest estimate
+/// of a window is the one that is closest to the played frames.
+#[derive(Debug, Default)]
+pub struct AnchorEstimator {
+    clock: Option<PlaybackClock>,
+    window_start: i64,
+    /// Latest estimate in the current window
+    latest: Option<i64>,
+    /// Latest estimate in the previous window
+    anchor: Option<i64>,
+}
+
+impl AnchorEstimator {
+    /// Estimate the anchor of `timeline` at `now`, a new playback run starts a new estimate
+    pub fn update(&mut self, timeline: Option<&Timeline>, now: i64) -> Option<i64> {
+        let timeline = match timeline {
+            Some(timeline) => timeline,
+            None => {
+                *self = AnchorEstimator::default();
+                return None;
+            }
+        };
+        if !self
+            .clock
+            .as_ref()
+            .is_some_and(|clock| clock.is_same(&timeline.clock))
+        {
+            *self = AnchorEstimator {
+                clock: Some(timeline.clock.clone()),
+                window_start: now,
+                ..AnchorEstimator::default()
+            };
+        }
+        let played = timeline.clock.frames() as f64 * 1e6 / timeline.sample_rate;
+        let estimate = now - played as i64;
+        self.latest = Some(self.latest.map_or(estimate, |latest| latest.max(estimate)));
+        if now - self.window_start >= ANCHOR_WINDOW {
+            self.anchor = self.latest.take();
+            self.window_start = now;
+        }
+        self.anchor.or(self.latest)
+    }
+
+    /// Take into account that the clock jumped `micros` forward
+    pub fn jumped(&mut self, micros: i64) {
+        for estimate in [&mut self.latest, &mut self.anchor].into_iter().flatten() {
+            *estimate -= micros;
+        }
+    }
+}
+
+/// NTP-like estimate of the offset between the leader's and the own clock
+#[derive(Debug, Default)]
+struct ClockOffset {
+    /// Round trip delay and offset of the last measurements
+    samples: VecDeque<(i64, i64)>,
+}
+
+impl ClockOffset {
+    /// Add the measurement of a ping that was sent at `t1`, received by the leader at `t2`,
+    /// answered at `t3` and whose answer was received at `t4`
+    fn add(&mut self, t1: i64, t2: i64, t3: i64, t4: i64) {
+        let delay = (t4 - t1) - (t3 - t2);
+        let offset = ((t2 - t1) + (t3 - t4)) / 2;
+        self.samples.push_back((delay, offset));
+        if self.samples.len() > OFFSET_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Leader time minus own time, from the measurement with the smallest delay, which was
+    /// disturbed the least by the network
+    fn offset(&self) -> Option<i64> {
+        self.samples.iter().min().map(|(_, offset)| *offset)
+    }
+}
+
+/// Leader, its timeline and the state that a follower applied
+#[derive(Debug, Default)]
+struct FollowerState {
+    /// Address of the leader and time of its last state message
+    leader: Option<(SocketAddr, Instant)>,
+    leader_anchor: Option<i64>,
+    offset: ClockOffset,
+    applied_playing: Option<bool>,
+    applied_settings: Vec<Entry>,
+}
+
+impl FollowerState {
+    /// REPL commands for the state message of `from` that was received at `now`
+    ///
+    /// The first sender becomes the leader, another one is only accepted when the leader has not
+    /// sent its state for 2 s.
+    fn receive_state(
+        &mut self,
+        from: SocketAddr,
+        anchor: Option<i64>,
+        settings: Vec<Entry>,
+        now: Instant,
+    ) -> Vec<String> {
+        let accepted = match self.leader {
+            Some((address, last_state)) => {
+                address == from || now.duration_since(last_state) >= LEADER_TIMEOUT
+            }
+            None => true,
+        };
+        if !accepted {
+            return Vec::new();
+        }
+        if self.leader.is_none_or(|(address, _)| address != from) {
+            self.offset = ClockOffset::default();
+        }
+        self.leader = Some((from, now));
+        self.leader_anchor = anchor;
+        let mut commands: Vec<String> = settings
+            .iter()
+            .filter(|entry| !self.applied_settings.contains(entry))
+            .map(|entry| format!("{} {}", entry.key, entry.value))
+            .collect();
+        if !settings.is_empty() {
+            self.applied_settings = settings;
+        }
+        let playing = anchor.is_some();
+        if self.applied_playing != Some(playing) {
+            match playing {
+                true => commands.push("start".to_string()),
+                false if self.applied_playing.is_some() => commands.push("stop".to_string()),
+                false => (),
+            }
+            self.applied_playing = Some(playing);
+        }
+        commands
+    }
+
+    /// Microseconds by which the own playback run, which played frame 0 at `own_anchor`, is
+    /// behind the leader's
+    fn behind(&self, own_anchor: i64) -> Option<i64> {
+        // a later own anchor means that the own playback is behind
+        Some(own_anchor - (self.leader_anchor? - self.offset.offset()?))
+    }
+}
+
+/// Shares the tempo, pattern and timeline of the playback with the followers on the local
+/// network and answers their pings
+pub struct SyncLeader {
+    _thread: StoppableThread,
+}
+
+impl SyncLeader {
+    /// Send the state of `timeline` to the multicast group on `port`
+    pub fn start(port: u16, timeline: SharedTimeline) -> Result<SyncLeader, String> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .map_err(|err| format!("Could not open a UDP socket: {}", err))?;
+        // followers on the same host receive the state, too
+        socket
+            .set_multicast_loop_v4(true)
+            .and_then(|_| socket.set_read_timeout(Some(POLL_INTERVAL)))
+            .map_err(|err| format!("Could not configure the UDP socket: {}", err))?;
+        let group = SocketAddrV4::new(SYNC_GROUP, port);
+        let thread = StoppableThread::spawn(move |stop| {
+            let epoch = Instant::now();
+            let mut estimator = AnchorEstimator::default();
+            let mut sent: Option<(Instant, bool, Vec<Entry>)> = None;
+            let mut buffer = [0; 1500];
+            while !stop.load(Ordering::Relaxed) {
+                if let Ok((size, from)) = socket.recv_from(&mut buffer) {
+                    let t2 = micros(epoch);
+                    if let Some(SyncMessage::Ping { t1 }) = SyncMessage::decode(&buffer[..size]) {
+                        let t3 = micros(epoch);
+                        let pong = SyncMessage::Pong { t1, t2, t3 }.encode();
+                        let _ = socket.send_to(pong.as_bytes(), from);
+                    }
+                }
+                let timeline = timeline.lock().unwrap().clone();
+                let anchor = estimator.update(timeline.as_ref(), micros(epoch));
+                let settings = timeline.map(|x| x.settings).unwrap_or_default();
+                // changes are sent immediately, the anchor estimate regularly
+                let due = match &sent {
+                    Some((time, playing, sent_settings)) => {
+                        time.elapsed() >= STATE_INTERVAL
+                            || *playing != anchor.is_some()
+                            || *sent_settings != settings
+                    }
+                    None => true,
+                };
+                if due {
+                    let state = SyncMessage::State {
+                        anchor,
+                        settings: settings.clone(),
+                    };
+                    // followers may appear later, so failures are ignored
+                    let _ = socket.send_to(state.encode().as_bytes(), group);
+                    sent = Some((Instant::now(), anchor.is_some(), settings));
+                }
+            }
+        });
+        Ok(SyncLeader { _thread: thread })
+    }
+}
+
+/// Follows a leader on the local network
+///
+/// Changes of the leader's settings and playback state become the REPL commands `bpm`, `value`,
+/// `pattern`, `start` and `stop`, state messages with other or invalid settings are dropped. The
+/// clock of the own playback is shifted to the leader's timeline when their phases differ by
+/// more than 5 ms. The follower trusts the first leader that it hears from and switches to
+/// another one after 2 s without state messages, so the sync is meant for trusted networks.
+pub struct SyncFollower {
+    _thread: StoppableThread,
+}
+
+impl SyncFollower {
+    /// Join the multicast group on `port` and align the playback run of `timeline`
+    pub fn start(
+        port: u16,
+        timeline: SharedTimeline,
+    ) -> Result<(SyncFollower, RemoteChannel), String> {
+        let group_socket = join_group(port)
+            .map_err(|err| format!("Could not join the sync group on port {}: {}", port, err))?;
+        let ping_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+            .map_err(|err| format!("Could not open a UDP socket: {}", err))?;
+        let (command_sender, commands) = mpsc::channel();
+        let (events, _) = mpsc::channel();
+        let thread = StoppableThread::spawn(move |stop| {
+            let send_command = |command_line: String| {
+                // nobody waits for the reply
+                let (reply, _) = mpsc::channel();
+                let _ = command_sender.send(RemoteCommand {
+                    command_line,
+                    reply,
+                });
+            };
+            let epoch = Instant::now();
+            let mut state = FollowerState::default();
+            let mut estimator = AnchorEstimator::default();
+            let mut last_ping: Option<Instant> = None;
+            let mut buffer = [0; 1500];
+            while !stop.load(Ordering::Relaxed) {
+                if let Ok((size, from)) = group_socket.recv_from(&mut buffer) {
+                    if let Some(SyncMessage::State { anchor, settings }) =
+                        SyncMessage::decode(&buffer[..size])
+                    {
+                        for command_line in
+                            state.receive_state(from, anchor, settings, Instant::now())
+                        {
+                            send_command(command_line);
+                        }
+                    }
+                }
+
+                if let Ok(size) = ping_socket.recv(&mut buffer) {
+                    let t4 = micros(epoch);
+                    if let Some(SyncMessage::Pong { t1, t2, t3 }) =
+                        SyncMessage::decode(&buffer[..size])
+                    {
+                        state.offset.add(t1, t2, t3, t4);
+                    }
+                }
+                if let Some((address, _)) = state.leader {
+                    if last_ping.is_none_or(|time| time.elapsed() >= PING_INTERVAL) {
+                        let ping = SyncMessage::Ping { t1: micros(epoch) }.encode();
+                        let _ = ping_socket.send_to(ping.as_bytes(), address);
+                        last_ping = Some(Instant::now());
+                    }
+                }
+
+                let timeline = timeline.lock().unwrap().clone();
+                let own_anchor = estimator.update(timeline.as_ref(), micros(epoch));
+                let behind = own_anchor.and_then(|own_anchor| state.behind(own_anchor));
+                if let (Some(timeline), Some(behind)) = (timeline, behind) {
+                    if behind.abs() > PHASE_TOLERANCE {
+                        let frames = behind as f64 * timeline.sample_rate / 1e6;
+                        timeline.clock.shift(frames.round() as i64);
+                        estimator.jumped(behind);
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Ok((
+            SyncFollower { _thread: thread },
+            RemoteChannel { commands, events },
+        ))
+    }
+}
+
+/// Socket that receives the datagrams of the multicast group on `port`, several instances on a
+/// host may receive them
+fn join_group(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    socket.join_multicast_v4(&SYNC_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    let socket: UdpSocket = socket.into();
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod test_netsync {
+    use super::*;
+
+    #[test]
+    fn test_sync_messages() {
+        let state = SyncMessage::State {
+            anchor: Some(-1500),
+            settings: vec![
+                Entry::new("bpm", "92.5".to_string()),
+                Entry::new("pattern", "!+++|!++".to_string()),
+            ],
+        };
+        assert_eq!(
+            state.encode(),
+            "mnomer-sync state -1500 bpm=92.5 pattern=!+++|!++"
+        );
+        assert_eq!(SyncMessage::decode(state.encode().as_bytes()), Some(state));
+        let stopped = SyncMessage::State {
+            anchor: None,
+            settings: Vec::new(),
+        };
+        assert_eq!(SyncMessage::decode(b"mnomer-sync state -"), Some(stopped));
+        assert_eq!(
+            SyncMessage::decode(b"mnomer-sync pong 1 2 3"),
+            Some(SyncMessage::Pong {
+                t1: 1,
+                t2: 2,
+                t3: 3
+            })
+        );
+        assert_eq!(SyncMessage::decode(b"mnomer-sync ping x"), None);
+        // only valid bpm, value and pattern settings are accepted
+        assert_eq!(SyncMessage::decode(b"mnomer-sync state - quit=now"), None);
+        assert_eq!(SyncMessage::decode(b"mnomer-sync state - bpm=-5"), None);
+        assert_eq!(SyncMessage::decode(b"mnomer-sync state - value=0"), None);
+        assert_eq!(SyncMessage::decode(b"mnomer-sync state - pattern=!x"), None);
+
+        // leader clock is 1000 ahead, the second measurement has the smaller delay
+        let mut offset = ClockOffset::default();
+        offset.add(0, 1400, 1410, 30);
+        offset.add(100, 1105, 1110, 120);
+        assert_eq!(offset.offset(), Some(997));
+    }
+
+    #[test]
+    fn test_anchor_estimator() {
+        let clock = PlaybackClock::new();
+        let timeline = Timeline {
+            clock: clock.clone(),
+            sample_rate: 48000.0,
+            settings: Vec::new(),
+        };
+        let mut estimator = AnchorEstimator::default();
+        // the audio callback is up to one buffer of 10 ms ahead, the latest estimate is used
+        clock.advance(480);
+        assert_eq!(estimator.update(Some(&timeline), 1_005_000), Some(995_000));
+        clock.advance(480);
+        assert_eq!(estimator.update(Some(&timeline), 1_015_000), Some(995_000));
+        assert_eq!(estimator.update(Some(&timeline), 1_019_000), Some(999_000));
+        // the estimate of a finished window is used during the next one
+        clock.advance(480 * 25);
+        assert_eq!(estimator.update(Some(&timeline), 1_255_000), Some(999_000));
+        assert_eq!(estimator.update(Some(&timeline), 1_260_000), Some(999_000));
+        estimator.jumped(4_000);
+        assert_eq!(estimator.update(Some(&timeline), 1_261_000), Some(995_000));
+
+        // a new playback run starts a new estimate
+        let timeline = Timeline {
+            clock: PlaybackClock::new(),
+            ..timeline
+        };
+        assert_eq!(
+            estimator.update(Some(&timeline), 2_000_000),
+            Some(2_000_000)
+        );
+        assert_eq!(estimator.update(None, 2_001_000), None);
+    }
+
+    #[test]
+    fn test_follower_state() {
+        let leader: SocketAddr = "192.168.1.2:40000".parse().unwrap();
+        let other: SocketAddr = "192.168.1.3:40000".parse().unwrap();
+        let settings = vec![
+            Entry::new("bpm", "120".to_string()),
+            Entry::new("pattern", "!+++".to_string()),
+        ];
+        let start = Instant::now();
+        let mut state = FollowerState::default();
+        assert_eq!(
+            state.receive_state(leader, None, settings.clone(), start),
+            ["bpm 120", "pattern !+++"]
+        );
+        assert_eq!(
+            state.receive_state(leader, Some(5_000), settings.clone(), start),
+            ["start"]
+        );
+        // another sender is ignored while the leader sends its state
+        let faster = vec![Entry::new("bpm", "180".to_string())];
+        let later = start + Duration::from_secs(1);
+        assert!(state
+            .receive_state(other, None, faster.clone(), later)
+            .is_empty());
+        assert_eq!(state.behind(0), None);
+
+        // leader clock is 1000 ahead, its playback started at own time 4000
+        state.offset.add(0, 1005, 1005, 10);
+        assert_eq!(state.behind(4_000), Some(0));
+        assert_eq!(state.behind(10_000), Some(6_000));
+        assert_eq!(state.behind(1_000), Some(-3_000));
+
+        // without state messages for 2 s another leader is accepted
+        let timeout = later + LEADER_TIMEOUT;
+        assert_eq!(
+            state.receive_state(other, None, faster, timeout),
+            ["bpm 180", "stop"]
+        );
+        assert_eq!(state.offset.offset(), None);
+    }
+
+    /// Advances `clock` like an audio callback with buffers of 10 ms at 48 kHz
+    fn play(clock: PlaybackClock) -> StoppableThread {
+        StoppableThread::spawn(move |stop| {
+            let start = Instant::now();
+            let mut advanced = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let due = (start.elapsed().as_secs_f64() * 100.0) as u64 * 480 + 480;
+                clock.advance(due - advanced);
+                advanced = due;
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+
+    #[test]
+    #[ignore = "binds the multicast port and takes more than a second"]
+    fn test_leader_and_follower() {
+        let port = DEFAULT_SYNC_PORT + 1;
+        let settings = vec![
+            Entry::new("bpm", "120".to_string()),
+            Entry::new("value", "4".to_string()),
+            Entry::new("pattern", "!+++".to_string()),
+        ];
+        let timeline = |clock: &PlaybackClock| {
+            Arc::new(Mutex::new(Some(Timeline {
+                clock: clock.clone(),
+                sample_rate: 48000.0,
+                settings: settings.clone(),
+            })))
+        };
+        let (leader_clock, follower_clock) = (PlaybackClock::new(), PlaybackClock::new());
+        let leader_playback = play(leader_clock.clone());
+        let _leader = SyncLeader::start(port, timeline(&leader_clock)).unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        let (_follower, channel) = SyncFollower::start(port, timeline(&follower_clock)).unwrap();
+        let follower_playback = play(follower_clock.clone());
+        thread::sleep(Duration::from_millis(1000));
+        let difference = leader_clock.frames() as i64 - follower_clock.frames() as i64;
+        drop((leader_playback, follower_playback));
+
+        let commands: Vec<String> = channel
+            .commands
+            .try_iter()
+            .map(|command| command.command_line)
+            .collect();
+        assert_eq!(commands, ["bpm 120", "value 4", "pattern !+++", "start"]);
+        // the follower jumped about 300 ms forward, 20 ms are one buffer of each and the tolerance
+        assert!(difference.abs() < 960, "{}", difference);
+    }
+}
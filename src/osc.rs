@@ -0,0 +1,348 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError},
+    },
+};
+
+use crate::{
+    remotecontrol::TCP_COMMANDS,
+    repl::repl::{RemoteChannel, RemoteCommand},
+    stoppablethread::{StoppableThread, POLL_INTERVAL},
+};
+
+/// Prefix of all addresses that mnomer understands and sends
+const ADDRESS_PREFIX: &str = "/mnomer/";
+
+/// Commands that take no arguments, buttons send 1 when pressed and 0 when released
+const BUTTON_COMMANDS: [&str; 2] = ["start", "stop"];
+
+/// Argument of an OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl std::fmt::Display for OscArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscArg::Int(value) => write!(f, "{}", value),
+            OscArg::Float(value) => write!(f, "{}", value),
+            OscArg::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// OSC 1.0 message
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    /// Encode the message as packet
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        push_string(&mut packet, &self.address);
+        let type_tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(|arg| match arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::Str(_) => 's',
+            }))
+            .collect();
+        push_string(&mut packet, &type_tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => packet.extend(value.to_be_bytes()),
+                OscArg::Float(value) => packet.extend(value.to_be_bytes()),
+                OscArg::Str(value) => push_string(&mut packet, value),
+            }
+        }
+        packet
+    }
+
+    /// Decode a packet, the messages of bundles are returned in their order
+    pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>, String> {
+        let mut reader = PacketReader { packet, pos: 0 };
+        let address = reader.string()?;
+        if address == "#bundle" {
+            // the time tag is ignored, the messages are executed immediately
+            reader.bytes(8)?;
+            let mut messages = Vec::new();
+            while reader.pos < packet.len() {
+                let size = reader.int()?;
+                let size = usize::try_from(size).map_err(|_| "Invalid bundle element size")?;
+                messages.extend(OscMessage::decode(reader.bytes(size)?)?);
+            }
+            return Ok(messages);
+        }
+        if !address.starts_with('/') {
+            return Err(format!("Invalid address \"{}\"", address));
+        }
+        // type tags are optional in old implementations
+        let type_tags = match reader.pos < packet.len() {
+            true => reader.string()?,
+            false => ",".to_string(),
+        };
+        let type_tags = type_tags
+            .strip_prefix(',')
+            .ok_or_else(|| "Invalid type tags".to_string())?;
+        let mut args = Vec::new();
+        for tag in type_tags.chars() {
+            args.push(match tag {
+                'i' => OscArg::Int(reader.int()?),
+                'f' => OscArg::Float(f32::from_bits(reader.int()? as u32)),
+                's' | 'S' => OscArg::Str(reader.string()?),
+                'T' => OscArg::Int(1),
+                'F' => OscArg::Int(0),
+                _ => return Err(format!("Unsupported argument type '{}'", tag)),
+            });
+        }
+        Ok(vec![OscMessage { address, args }])
+    }
+}
+
+/// Append a null terminated string padded to a multiple of 4 bytes
+fn push_string(packet: &mut Vec<u8>, value: &str) {
+    packet.extend(value.as_bytes());
+    packet.extend(std::iter::repeat_n(0, 4 - value.len() % 4));
+}
+
+struct PacketReader<'a> {
+    packet: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PacketReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .packet
+            .get(self.pos..self.pos.saturating_add(count))
+            .ok_or_else(|| "Packet is too short".to_string())?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.packet[self.pos.min(self.packet.len())..];
+        let len = rest
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or_else(|| "Unterminated string".to_string())?;
+        let value = String::from_utf8(rest[..len].to_vec()).map_err(|_| "Invalid UTF-8")?;
+        self.bytes((len / 4 + 1) * 4)?;
+        Ok(value)
+    }
+}
+
+/// REPL command line of a message, `None` for messages that are ignored
+///
+/// `/mnomer/<command> <args>` becomes `<command> <args>`, e.g. `/mnomer/bpm 120.0` sets the bpm.
+/// Only the commands that TCP clients may send are accepted.
+pub fn command_line(message: &OscMessage) -> Option<String> {
+    let command = message.address.strip_prefix(ADDRESS_PREFIX)?;
+    if !TCP_COMMANDS.contains(&command) {
+        return None;
+    }
+    if BUTTON_COMMANDS.contains(&command) {
+        let released = matches!(
+            message.args.first(),
+            Some(OscArg::Int(0)) | Some(OscArg::Float(0.0))
+        );
+        return (!released).then(|| command.to_string());
+    }
+    let args: Vec<String> = message.args.iter().map(|arg| arg.to_string()).collect();
+    Some(
+        format!("{} {}", command, args.join(" "))
+            .trim_end()
+            .to_string(),
+    )
+}
+
+/// `/mnomer/beat` message with bar, beat and beat type of a `beat` event
+fn beat_message(event: &str) -> Option<OscMessage> {
+    let mut fields = event.strip_prefix("beat ")?.split_whitespace().skip(1);
+    let (bar, beat) = fields.next()?.split_once(':')?;
+    Some(OscMessage {
+        address: format!("{}beat", ADDRESS_PREFIX),
+        args: vec![
+            OscArg::Int(bar.parse().ok()?),
+            OscArg::Int(beat.parse().ok()?),
+            OscArg::Str(fields.next()?.to_string()),
+        ],
+    })
+}
+
+/// Receives OSC messages and sends `/mnomer/beat` messages, the port is closed when it is dropped
+pub struct OscServer {
+    _receiver: StoppableThread,
+    _sender: StoppableThread,
+}
+
+impl OscServer {
+    /// Receive OSC messages on localhost port `port` and send `/mnomer/beat` messages to `send_to`
+    ///
+    /// Invalid packets and unknown addresses are ignored, errors of commands are shown by the REPL.
+    pub fn start(port: u16, send_to: Option<&str>) -> Result<(OscServer, RemoteChannel), String> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|err| format!("Could not listen on UDP port {}: {}", port, err))?;
+        socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|err| format!("Could not configure the UDP socket: {}", err))?;
+        let target = send_to.map(parse_target).transpose()?;
+        let (command_sender, commands) = mpsc::channel();
+        let (events, event_receiver) = mpsc::channel::<String>();
+
+        // the target may be on another host, which a localhost socket cannot reach
+        let sender = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .map_err(|err| format!("Could not open a UDP socket: {}", err))?;
+        let sender_thread = StoppableThread::spawn(move |stop| {
+            while !stop.load(Ordering::Relaxed) {
+                let event = match event_receiver.recv_timeout(POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                if let (Some(target), Some(message)) = (target, beat_message(&event)) {
+                    // nobody may listen at the target
+                    let _ = sender.send_to(&message.encode(), target);
+                }
+            }
+        });
+        let receiver_thread = StoppableThread::spawn(move |stop| {
+            let mut buffer = [0; 65536];
+            while !stop.load(Ordering::Relaxed) {
+                let messages = match socket.recv(&mut buffer) {
+                    Ok(size) => OscMessage::decode(&buffer[..size]).unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                for command_line in messages.iter().filter_map(command_line) {
+                    // the result is shown in the REPL, nobody waits for the reply
+                    let (reply, _) = mpsc::channel();
+                    let command = RemoteCommand {
+                        command_line,
+                        reply,
+                    };
+                    if command_sender.send(command).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok((
+            OscServer {
+                _receiver: receiver_thread,
+                _sender: sender_thread,
+            },
+            RemoteChannel { commands, events },
+        ))
+    }
+}
+
+/// Target like `9000`, `localhost:9000` or `192.168.1.5:9000`
+fn parse_target(target: &str) -> Result<SocketAddr, String> {
+    let error = || format!("\"{}\" is not a port or host:port", target);
+    if let Ok(port) = target.parse::<u16>() {
+        return Ok((Ipv4Addr::LOCALHOST, port).into());
+    }
+    target
+        .to_socket_addrs()
+        .map_err(|_| error())?
+        .next()
+        .ok_or_else(error)
+}
+
+#[cfg(test)]
+mod test_osc {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let message = OscMessage {
+            address: "/mnomer/pattern".to_string(),
+            args: vec![
+                OscArg::Str("!+++".to_string()),
+                OscArg::Float(92.5),
+                OscArg::Int(-3),
+            ],
+        };
+        let packet = message.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(&packet[..20], b"/mnomer/pattern\0,sfi");
+        assert_eq!(OscMessage::decode(&packet).unwrap(), vec![message.clone()]);
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        bundle.extend((packet.len() as i32).to_be_bytes());
+        bundle.extend(&packet);
+        assert_eq!(OscMessage::decode(&bundle).unwrap(), vec![message]);
+        assert!(OscMessage::decode(&packet[..packet.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let message = |address: &str, args| OscMessage {
+            address: address.to_string(),
+            args,
+        };
+        assert_eq!(
+            command_line(&message("/mnomer/bpm", vec![OscArg::Float(120.0)])),
+            Some("bpm 120".to_string())
+        );
+        assert_eq!(
+            command_line(&message("/mnomer/start", vec![OscArg::Float(1.0)])),
+            Some("start".to_string())
+        );
+        assert_eq!(
+            command_line(&message("/mnomer/stop", vec![OscArg::Float(0.0)])),
+            None
+        );
+        assert_eq!(command_line(&message("/other/bpm", Vec::new())), None);
+        let path = OscArg::Str("/tmp/click.mid".to_string());
+        assert_eq!(
+            command_line(&message("/mnomer/export-midi", vec![path])),
+            None
+        );
+        assert_eq!(command_line(&message("/mnomer/quit", Vec::new())), None);
+        assert_eq!(
+            beat_message("beat 1.500 3:2 !").unwrap().args,
+            vec![OscArg::Int(3), OscArg::Int(2), OscArg::Str("!".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_server() {
+        let port = 47913;
+        let (server, channel) = OscServer::start(port, None).unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        for (address, args) in [
+            ("/mnomer/quit", vec![]),
+            ("/mnomer/bpm", vec![OscArg::Int(90)]),
+        ] {
+            let message = OscMessage {
+                address: address.to_string(),
+                args,
+            };
+            client
+                .send_to(&message.encode(), (Ipv4Addr::LOCALHOST, port))
+                .unwrap();
+        }
+        let command = channel
+            .commands
+            .recv_timeout(std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(command.command_line, "bpm 90");
+
+        // the port is free again when the server is dropped
+        drop(server);
+        OscServer::start(port, None).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::convert::TryFrom;
+
+use crate::tuning::Tuning;
+
+/// Default reference pitch of A4 in Hz
+pub const DEFAULT_A4: f64 = 440.0;
+
+/// MIDI note number of A4
+const A4_NOTE: i32 = 69;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Pitch as given by the user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PitchSpec {
+    /// Absolute frequency in Hz, e.g. `440` or `440Hz`
+    Frequency(f64),
+    /// MIDI note number from a note name with octave, e.g. `A4` or `Bb3`
+    Note(i32),
+    /// Scale steps relative to a reference pitch, e.g. `+5`, `-2` or `0`
+    Relative(f64),
+}
+
+impl TryFrom<&str> for PitchSpec {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let error = || {
+            format!(
+                "\"{}\" is neither a frequency, a note nor a scale step offset",
+                value
+            )
+        };
+        if value.starts_with(['+', '-']) || value == "0" {
+            value
+                .parse::<f64>()
+                .map(PitchSpec::Relative)
+                .map_err(|_| error())
+        } else if value.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            parse_note(value).map(PitchSpec::Note).ok_or_else(error)
+        } else {
+            let number = value
+                .strip_suffix("Hz")
+                .or(value.strip_suffix("hz"))
+                .unwrap_or(value);
+            number
+                .parse::<f64>()
+                .map(PitchSpec::Frequency)
+                .map_err(|_| error())
+        }
+    }
+}
+
+impl PitchSpec {
+    /// Frequency in Hz, note names resolve through `tuning` and relative pitches are scale steps
+    /// above `reference`
+    pub fn frequency(&self, reference: f64, tuning: &Tuning, a4: f64) -> Result<f64, String> {
+        match self {
+            PitchSpec::Frequency(frequency) => Ok(*frequency),
+            PitchSpec::Note(note) => tuning.frequency(*note, a4),
+            PitchSpec::Relative(steps) => Ok(reference * tuning.steps_ratio(*steps)),
+        }
+    }
+
+    /// Frequency of the pitch `steps` scale steps above this pitch
+    pub fn interval_frequency(
+        &self,
+        steps: f64,
+        reference: f64,
+        tuning: &Tuning,
+        a4: f64,
+    ) -> Result<f64, String> {
+        match self {
+            PitchSpec::Note(note) if steps.fract() == 0.0 => {
+                tuning.frequency(*note + steps as i32, a4)
+            }
+            _ => Ok(self.frequency(reference, tuning, a4)? * tuning.steps_ratio(steps)),
+        }
+    }
+}
+
+/// Parse a note name with octave like `C4`, `F#3`, `Bb5` or `C-1` to a MIDI note number
+pub fn parse_note(value: &str) -> Option<i32> {
+    let mut chars = value.chars();
+    let semitone = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix(['#', '♯']) {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix(['b', '♭']) {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let octave = octave.parse::<i32>().ok()?;
+    Some((octave + 1) * 12 + semitone + accidental)
+}
+
+/// Note names with octave from C1 to B7, e.g. as completion candidates
+pub fn note_names() -> Vec<String> {
+    (1..=7)
+        .flat_map(|octave| {
+            NOTE_NAMES
+                .iter()
+                .map(move |name| format!("{}{}", name, octave))
+        })
+        .collect()
+}
+
+/// Name of the nearest note in equal temperament, with the deviation in cents if it is at least
+/// one cent
+pub fn note_name(frequency: f64, a4: f64) -> String {
+    let semitones = 12.0 * (frequency / a4).log2();
+    let nearest = semitones.round();
+    let note = nearest as i32 + A4_NOTE;
+    let name = format!(
+        "{}{}",
+        NOTE_NAMES[note.rem_euclid(12) as usize],
+        note.div_euclid(12) - 1
+    );
+    let cents = ((semitones - nearest) * 100.0).round();
+    if cents.abs() >= 1.0 {
+        format!("{}{:+}c", name, cents)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test_pitch {
+    use super::*;
+
+    #[test]
+    fn test_parse_note() {
+        assert_eq!(parse_note("A4"), Some(69));
+        assert_eq!(parse_note("C4"), Some(60));
+        assert_eq!(parse_note("c#4"), Some(61));
+        assert_eq!(parse_note("Bb3"), Some(58));
+        assert_eq!(parse_note("C-1"), Some(0));
+        assert_eq!(parse_note("H4"), None);
+        assert_eq!(parse_note("A"), None);
+    }
+
+    #[test]
+    fn test_pitch_spec() {
+        assert_eq!(PitchSpec::try_from("440"), Ok(PitchSpec::Frequency(440.0)));
+        assert_eq!(
+            PitchSpec::try_from("415.3Hz"),
+            Ok(PitchSpec::Frequency(415.3))
+        );
+        assert_eq!(PitchSpec::try_from("D5"), Ok(PitchSpec::Note(74)));
+        assert_eq!(PitchSpec::try_from("+5"), Ok(PitchSpec::Relative(5.0)));
+        assert_eq!(PitchSpec::try_from("-2"), Ok(PitchSpec::Relative(-2.0)));
+        assert_eq!(PitchSpec::try_from("0"), Ok(PitchSpec::Relative(0.0)));
+        assert!(PitchSpec::try_from("loud").is_err());
+
+        let tuning = Tuning::default();
+        let frequency = PitchSpec::Relative(12.0).frequency(220.0, &tuning, DEFAULT_A4);
+        assert!((frequency.unwrap() - 440.0).abs() < 1e-9);
+        let frequency = PitchSpec::Note(69).frequency(220.0, &tuning, 442.0);
+        assert!((frequency.unwrap() - 442.0).abs() < 1e-9);
+
+        // a just fifth above D4
+        let tuning = Tuning::temperament("just", 62).unwrap();
+        let d4 = PitchSpec::Note(62);
+        let frequency = d4.interval_frequency(7.0, 220.0, &tuning, DEFAULT_A4);
+        assert!((frequency.unwrap() - DEFAULT_A4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_note_name() {
+        assert_eq!(note_name(440.0, DEFAULT_A4), "A4");
+        assert_eq!(note_name(587.33, DEFAULT_A4), "D5");
+        assert_eq!(note_name(442.0, DEFAULT_A4), "A4+8c");
+        assert_eq!(note_name(442.0, 442.0), "A4");
+        assert_eq!(note_name(8.1758, DEFAULT_A4), "C-1");
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Resolution of the tick part of a `MusicalPosition`
+pub const TICKS_PER_BEAT: u64 = 960;
+
+/// Audio clock that is advanced by the playback stream
+///
+/// Counts the frames (samples per channel) that the audio callback has written to the device. It
+/// also carries the end of the session, which the audio callback uses to fade out the playback.
+#[derive(Debug, Clone)]
+pub struct PlaybackClock {
+    frames: Arc<AtomicU64>,
+    end_frame: Arc<AtomicU64>,
+    fade_frames: Arc<AtomicU64>,
+    finished: Arc<AtomicBool>,
+}
+
+impl Default for PlaybackClock {
+    fn default() -> Self {
+        PlaybackClock {
+            frames: Arc::new(AtomicU64::new(0)),
+            end_frame: Arc::new(AtomicU64::new(u64::MAX)),
+            fade_frames: Arc::new(AtomicU64::new(0)),
+            finished: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl PlaybackClock {
+    pub fn new() -> PlaybackClock {
+        PlaybackClock::default()
+    }
+
+    /// Called from the audio callback after `frames` have been written
+    pub fn advance(&self, frames: u64) {
+        self.frames.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Jump `frames` forward or backward, e.g. to follow another timeline
+    ///
+    /// The playback continues at the new position, backward jumps end at frame 0.
+    pub fn shift(&self, frames: i64) {
+        let frames = frames.max(-(self.frames() as i64));
+        self.frames.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    /// Whether `other` is a clone of this clock
+    pub fn is_same(&self, other: &PlaybackClock) -> bool {
+        Arc::ptr_eq(&self.frames, &other.frames)
+    }
+
+    /// Frames played until now or until the end of the session
+    pub fn played_frames(&self) -> u64 {
+        self.frames().min(self.end_frame().saturating_sub(1))
+    }
+
+    /// End the session at `end_frame`, fading out over the `fade_frames` before it
+    pub fn set_end(&self, end_frame: u64, fade_frames: u64) {
+        self.fade_frames.store(fade_frames, Ordering::Relaxed);
+        self.end_frame.store(end_frame, Ordering::Relaxed);
+    }
+
+    pub fn clear_end(&self) {
+        self.end_frame.store(u64::MAX, Ordering::Relaxed);
+    }
+
+    pub fn end_frame(&self) -> u64 {
+        self.end_frame.load(Ordering::Relaxed)
+    }
+
+    pub fn fade_frames(&self) -> u64 {
+        self.fade_frames.load(Ordering::Relaxed)
+    }
+
+    /// Called from the audio callback when everything after the session end has been played
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    /// Gain of the playback at `frame` with regard to the session end
+    pub fn gain(&self, frame: u64) -> f32 {
+        let end_frame = self.end_frame();
+        let fade_frames = self.fade_frames();
+        if frame >= end_frame {
+            0.0
+        } else if frame + fade_frames >= end_frame {
+            (end_frame - frame) as f32 / fade_frames as f32
+        } else {
+            1.0
+        }
+    }
+}
+
+/// First frame of the beat with index `beat`
+///
+/// The position is computed from the beat index instead of accumulating the beat lengths so that
+/// rounding errors do not add up.
+pub fn beat_onset(beat: u64, samples_per_beat: f64) -> u64 {
+    (beat as f64 * samples_per_beat).round() as u64
+}
+
+/// Index of the beat that is played at `frame`
+pub fn beat_at_frame(frame: u64, samples_per_beat: f64) -> u64 {
+    let beat = ((frame as f64 + 0.5) / samples_per_beat).floor() as u64;
+    if beat > 0 && beat_onset(beat, samples_per_beat) > frame {
+        beat - 1
+    } else {
+        beat
+    }
+}
+
+/// Number of samples of a beat at `bpm`, which is based on the beat value 1/4
+pub fn samples_per_beat(bpm: f64, beat_value: u16, sample_rate: f64) -> f64 {
+    60.0 * sample_rate / (bpm * beat_value as f64 / 4.0)
+}
+
+/// Frames of the beats of a playback run, either with a constant tempo or following a cycle of
+/// sections with different tempos
+#[derive(Debug, Clone)]
+pub struct BeatGrid {
+    /// First beat, first frame and samples per beat of each section
+    sections: Vec<(u64, u64, f64)>,
+    /// Beats and frames of the cycle of sections, `None` for a constant tempo
+    cycle: Option<(u64, u64)>,
+}
+
+impl BeatGrid {
+    pub fn constant(samples_per_beat: f64) -> BeatGrid {
+        BeatGrid {
+            sections: vec![(0, 0, samples_per_beat)],
+            cycle: None,
+        }
+    }
+
+    /// Cycle of sections given as number of beats and samples per beat
+    ///
+    /// Every section starts at a whole frame, so the onsets within a section do not drift.
+    pub fn cycle(sections: &[(u64, f64)]) -> Result<BeatGrid, String> {
+        let mut grid = Vec::new();
+        let (mut beat, mut frame) = (0, 0);
+        for &(beats, samples_per_beat) in sections.iter().filter(|(beats, _)| *beats > 0) {
+            grid.push((beat, frame, samples_per_beat));
+            beat += beats;
+            frame += beat_onset(beats, samples_per_beat);
+        }
+        if beat == 0 || frame == 0 {
+            return Err("A cycle of sections needs at least one beat".to_string());
+        }
+        Ok(BeatGrid {
+            sections: grid,
+            cycle: Some((beat, frame)),
+        })
+    }
+
+    /// First frame of the beat with index `beat`
+    pub fn onset(&self, beat: u64) -> u64 {
+        let (cycle_frame, beat) = match self.cycle {
+            Some((beats, frames)) => (beat / beats * frames, beat % beats),
+            None => (0, beat),
+        };
+        let index = self.sections.partition_point(|section| section.0 <= beat) - 1;
+        let (first_beat, first_frame, samples_per_beat) = self.sections[index];
+        cycle_frame + first_frame + beat_onset(beat - first_beat, samples_per_beat)
+    }
+
+    /// Index of the beat that is played at `frame`
+    pub fn beat_at_frame(&self, frame: u64) -> u64 {
+        let (cycle_beat, frame) = match self.cycle {
+            Some((beats, frames)) => (frame / frames * beats, frame % frames),
+            None => (0, frame),
+        };
+        let index = self.sections.partition_point(|section| section.1 <= frame) - 1;
+        let (first_beat, first_frame, samples_per_beat) = self.sections[index];
+        cycle_beat + first_beat + beat_at_frame(frame - first_frame, samples_per_beat)
+    }
+
+    /// Number of samples of the shortest beat
+    pub fn min_samples_per_beat(&self) -> f64 {
+        self.sections
+            .iter()
+            .map(|section| section.2)
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// Position within the played beats as bar:beat:tick, bar and beat start with 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MusicalPosition {
+    pub bar: u64,
+    pub beat: u64,
+    pub tick: u64,
+}
+
+impl Display for MusicalPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{:03}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// Format a duration as `MM:SS` or `H:MM:SS` if it lasts longer than an hour
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod test_playbackclock {
+    use super::*;
+
+    #[test]
+    fn test_beat_at_frame() {
+        // 93.5 bpm at 48kHz does not have an integer number of samples per beat
+        let samples_per_beat = 60.0 * 48000.0 / 93.5;
+        for beat in (0..1_000_000).step_by(997) {
+            let onset = beat_onset(beat, samples_per_beat);
+            assert_eq!(beat_at_frame(onset, samples_per_beat), beat);
+            if onset > 0 {
+                assert_eq!(beat_at_frame(onset - 1, samples_per_beat), beat - 1);
+            }
+        }
+        // no drift after many beats
+        let onset = beat_onset(1_000_000, samples_per_beat);
+        assert_eq!(onset, 30_802_139_037);
+    }
+
+    #[test]
+    fn test_beat_grid() {
+        let constant = BeatGrid::constant(1000.5);
+        assert_eq!(constant.onset(3), beat_onset(3, 1000.5));
+        assert_eq!(constant.beat_at_frame(3002), 3);
+
+        // 2 beats of 1000 frames and 3 beats of 500.5 frames
+        let grid = BeatGrid::cycle(&[(2, 1000.0), (3, 500.5)]).unwrap();
+        let onsets = [0, 1000, 2000, 2501, 3001, 3502, 4502];
+        for (beat, onset) in onsets.iter().enumerate() {
+            assert_eq!(grid.onset(beat as u64), *onset);
+            assert_eq!(grid.beat_at_frame(*onset), beat as u64);
+            if *onset > 0 {
+                assert_eq!(grid.beat_at_frame(onset - 1), beat as u64 - 1);
+            }
+        }
+        assert_eq!(grid.min_samples_per_beat(), 500.5);
+        assert!(BeatGrid::cycle(&[(0, 1000.0)]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+/// Named combination of pattern, beat value and suggested tempo
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub pattern: &'static str,
+    pub beat_value: u16,
+    pub bpm: f64,
+}
+
+/// Library of common patterns and meters
+pub const PRESETS: [Preset; 8] = [
+    Preset {
+        name: "waltz",
+        description: "3/4 waltz",
+        pattern: "!++",
+        beat_value: 4,
+        bpm: 90.0,
+    },
+    Preset {
+        name: "6/8",
+        description: "6/8 in two, clicks on the dotted quarters",
+        pattern: "!..+..",
+        beat_value: 8,
+        bpm: 90.0,
+    },
+    Preset {
+        name: "7/8",
+        description: "7/8 grouped 2+2+3, clicks on the groups",
+        pattern: "!.+.+..",
+        beat_value: 8,
+        bpm: 100.0,
+    },
+    Preset {
+        name: "5/4",
+        description: "5/4 grouped 3+2",
+        pattern: "!++ ++",
+        beat_value: 4,
+        bpm: 120.0,
+    },
+    Preset {
+        name: "son-3-2",
+        description: "son clave 3-2 in eighths over two bars",
+        pattern: "!..+..+.|..+.+...",
+        beat_value: 8,
+        bpm: 100.0,
+    },
+    Preset {
+        name: "rumba-3-2",
+        description: "rumba clave 3-2 in eighths over two bars",
+        pattern: "!..+...+|..+.+...",
+        beat_value: 8,
+        bpm: 100.0,
+    },
+    Preset {
+        name: "bossa",
+        description: "bossa nova clave in eighths over two bars",
+        pattern: "!..+..+.|..+..+..",
+        beat_value: 8,
+        bpm: 130.0,
+    },
+    Preset {
+        name: "shuffle",
+        description: "4/4 shuffle in eighth triplets",
+        pattern: "(!.+)(+.+)x3",
+        beat_value: 12,
+        bpm: 100.0,
+    },
+];
+
+/// Preset with the given name
+pub fn find_preset(name: &str) -> Option<&'static Preset> {
+    PRESETS
+        .iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod test_presets {
+    use super::*;
+    use crate::beatplayer::BeatPattern;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for preset in &PRESETS {
+            let pattern = BeatPattern::try_from(preset.pattern).unwrap();
+            assert!(!pattern.pattern.is_empty(), "{}", preset.name);
+            assert!(preset.beat_value > 0 && preset.bpm > 0.0);
+        }
+        assert_eq!(find_preset("Waltz").unwrap().pattern, "!++");
+        assert!(find_preset("polka").is_none());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::repl::repl::{RemoteChannel, RemoteCommand};
+
+/// Commands that TCP clients and OSC messages may send
+///
+/// Every local user can connect to the TCP port or send to the OSC port, so commands that write
+/// files, read files named by the client or quit the app are only accepted on the Unix domain
+/// socket.
+pub const TCP_COMMANDS: [&str; 20] = [
+    "start",
+    "stop",
+    "bpm",
+    "value",
+    "pattern",
+    "preset",
+    "load",
+    "list",
+    "device",
+    "silent",
+    "bell",
+    "statusline",
+    "pitch",
+    "reference",
+    "drone",
+    "notenames",
+    "reset",
+    "timer",
+    "status",
+    "help",
+];
+
+/// Writers of the clients that subscribed to the events
+type Subscribers = Arc<Mutex<Vec<Arc<Mutex<dyn Write + Send>>>>>;
+
+/// Default path of the control socket, in `$XDG_RUNTIME_DIR` if it is set
+pub fn default_socket_path() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    match std::env::var("USER") {
+        Ok(user) if !user.is_empty() => dir.join(format!("mnomer-{}.sock", user)),
+        _ => dir.join("mnomer.sock"),
+    }
+}
+
+/// Line based remote control of the REPL over a Unix domain socket and localhost TCP
+///
+/// Every line that a client sends is a command line that is answered with one line, either
+/// `ok <message>` or `error <message>`, newlines in messages are sent as `\n`. After the command
+/// `subscribe` the client also receives the events of the app as `event <event>` lines.
+pub struct RemoteControl {
+    socket_path: Option<PathBuf>,
+}
+
+impl RemoteControl {
+    /// Listen on the Unix domain socket `socket_path` and/or on localhost port `tcp_port`
+    pub fn start(
+        socket_path: Option<PathBuf>,
+        tcp_port: Option<u16>,
+    ) -> Result<(RemoteControl, RemoteChannel), String> {
+        let (command_sender, commands) = mpsc::channel();
+        let (events, event_receiver) = mpsc::channel();
+        let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+
+        if let Some(port) = tcp_port {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+                .map_err(|err| format!("Could not listen on port {}: {}", port, err))?;
+            let (command_sender, subscribers) = (command_sender.clone(), subscribers.clone());
+            thread::spawn(move || {
+                for connection in listener.incoming().flatten() {
+                    serve(
+                        connection,
+                        command_sender.clone(),
+                        subscribers.clone(),
+                        true,
+                    );
+                }
+            });
+        }
+        if let Some(path) = &socket_path {
+            listen_unix(path, command_sender, subscribers.clone())?;
+        }
+        thread::spawn(move || distribute_events(event_receiver, subscribers));
+
+        Ok((
+            RemoteControl { socket_path },
+            RemoteChannel { commands, events },
+        ))
+    }
+}
+
+impl Drop for RemoteControl {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(
+    path: &PathBuf,
+    command_sender: Sender<RemoteCommand>,
+    subscribers: Subscribers,
+) -> Result<(), String> {
+    // a socket file without a listener is left over from a previous run
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is used by another instance", path.display()));
+        }
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|err| format!("Could not listen on {}: {}", path.display(), err))?;
+    thread::spawn(move || {
+        for connection in listener.incoming().flatten() {
+            serve(
+                connection,
+                command_sender.clone(),
+                subscribers.clone(),
+                false,
+            );
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(
+    _path: &PathBuf,
+    _command_sender: Sender<RemoteCommand>,
+    _subscribers: Subscribers,
+) -> Result<(), String> {
+    Err("Unix domain sockets are not supported on this platform, use TCP".to_string())
+}
+
+/// Connection of a client
+trait Connection: Read + Write + Send + Sized + 'static {
+    fn duplicate(&self) -> io::Result<Self>;
+}
+
+impl Connection for TcpStream {
+    fn duplicate(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn duplicate(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+/// Serve a client in its own thread, a `restricted` client may only send the `TCP_COMMANDS`
+fn serve<C: Connection>(
+    connection: C,
+    command_sender: Sender<RemoteCommand>,
+    subscribers: Subscribers,
+    restricted: bool,
+) {
+    thread::spawn(move || -> io::Result<()> {
+        // responses and events must not interleave
+        let writer = Arc::new(Mutex::new(connection.duplicate()?));
+        for line in BufReader::new(connection).lines() {
+            let line = line?;
+            let command_line = line.trim();
+            if command_line.is_empty() {
+                continue;
+            }
+            let command = command_line.split_whitespace().next().unwrap_or_default();
+            let response = if command_line == "subscribe" {
+                subscribers.lock().unwrap().push(writer.clone());
+                "ok subscribed".to_string()
+            } else if restricted && !TCP_COMMANDS.contains(&command) {
+                format!(
+                    "error \"{}\" is only accepted on the Unix domain socket",
+                    command
+                )
+            } else {
+                let (reply, result) = mpsc::channel();
+                let command = RemoteCommand {
+                    command_line: command_line.to_string(),
+                    reply,
+                };
+                if command_sender.send(command).is_err() {
+                    break;
+                }
+                match result.recv() {
+                    Ok(Ok(msg)) => format!("ok {}", escape(&msg)),
+                    Ok(Err(msg)) => format!("error {}", escape(&msg)),
+                    Err(_) => break,
+                }
+            };
+            writeln!(writer.lock().unwrap(), "{}", response)?;
+        }
+        Ok(())
+    });
+}
+
+/// Send every event to the subscribers and forget the disconnected ones
+fn distribute_events(events: Receiver<String>, subscribers: Subscribers) {
+    for event in events {
+        subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| writeln!(subscriber.lock().unwrap(), "event {}", event).is_ok());
+    }
+}
+
+/// Keep a message on one line
+fn escape(msg: &str) -> String {
+    msg.replace("\n\r", "\n").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test_remotecontrol {
+    use super::*;
+
+    #[test]
+    fn test_tcp_commands_and_events() {
+        // port 0 is not known to the client, so find a free port first
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (_remote, channel) = RemoteControl::start(None, Some(port)).unwrap();
+        let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+
+        writeln!(client, "subscribe").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "ok subscribed");
+
+        writeln!(client, "bpm 120").unwrap();
+        let command = channel.commands.recv().unwrap();
+        assert_eq!(command.command_line, "bpm 120");
+        command
+            .reply
+            .send(Ok("Bpm set\nto 120".to_string()))
+            .unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "ok Bpm set\\nto 120");
+
+        channel.events.send("state playing".to_string()).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "event state playing");
+
+        writeln!(client, "bpm x").unwrap();
+        let command = channel.commands.recv().unwrap();
+        command.reply.send(Err("No number".to_string())).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "error No number");
+
+        // commands that write files or quit are not forwarded
+        for command_line in [
+            "config write /tmp/x.ini",
+            "export-midi /tmp/x.mid 4",
+            "quit",
+        ] {
+            writeln!(client, "{}", command_line).unwrap();
+            assert!(lines
+                .next()
+                .unwrap()
+                .unwrap()
+                .ends_with("is only accepted on the Unix domain socket"));
+        }
+        assert!(channel.commands.try_recv().is_err());
+    }
+}
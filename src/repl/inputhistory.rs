@@ -1,3 +1,42 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// File to which the entered lines are appended, shared by all running instances
+#[derive(Debug, PartialEq)]
+struct HistoryFile {
+    path: PathBuf,
+    /// Number of lines that are kept
+    limit: usize,
+}
+
+impl HistoryFile {
+    /// Exclusive lock of the history, which is released when the returned file is closed
+    ///
+    /// The lock is taken on a separate file because shortening replaces the history file.
+    fn lock(&self) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        // other instances must not shorten the file in the meantime
+        let _lock = self.lock()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(format!("{}\n", line).as_bytes())
+    }
+}
+
 /// Represent command history
 ///
 /// Implements a virtual cursor (row, column) and provides keystroke implementations for cursor navigation
@@ -11,6 +50,8 @@ pub struct InputHistory {
     row: usize,
     /// Cursor column so that we know where to put in the character
     column: usize,
+    /// File that keeps the history across sessions
+    file: Option<HistoryFile>,
 }
 
 impl InputHistory {
@@ -21,7 +62,43 @@ impl InputHistory {
             writing_buffer: vec![],
             row: 0,
             column: 0,
+            file: None,
+        }
+    }
+
+    /// Load the last `limit` lines of the history file at `path` and append entered lines to it
+    ///
+    /// A longer file is shortened to `limit` lines. A missing file is created with the first line.
+    /// Empty lines, lines starting with a space and repeated lines are not recorded then.
+    pub fn load_file(&mut self, path: PathBuf, limit: usize) -> Result<(), String> {
+        let file = HistoryFile { path, limit };
+        let path = &file.path;
+        let error =
+            |err: io::Error| format!("Could not load history \"{}\": {}", path.display(), err);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
         }
+        // lines that other instances append until the file is replaced are kept
+        let lock = file.lock().map_err(error)?;
+        let content = match fs::read(path) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(error(err)),
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        let kept = &lines[lines.len().saturating_sub(limit)..];
+        if kept.len() < lines.len() {
+            // replaced at once, so that other instances never see a partial file
+            let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+            fs::write(&temporary, kept.join("\n") + "\n").map_err(error)?;
+            fs::rename(&temporary, path).map_err(error)?;
+        }
+        drop(lock);
+        self.previous_lines = kept.iter().map(|line| line.chars().collect()).collect();
+        self.row = self.previous_lines.len();
+        self.column = 0;
+        self.file = Some(file);
+        Ok(())
     }
 
     #[cfg(test)]
@@ -72,7 +149,22 @@ impl InputHistory {
     pub fn add_line(&mut self) -> bool {
         self._prepare_modifying_access();
         let current_line = std::mem::take(&mut self.writing_buffer);
-        self.previous_lines.push(current_line);
+        match &self.file {
+            // empty lines and lines starting with a space are not recorded, repeated lines once
+            Some(file) => {
+                if !current_line.is_empty()
+                    && current_line[0] != ' '
+                    && self.previous_lines.last() != Some(&current_line)
+                {
+                    // losing a line of the history is not worth interrupting the input
+                    let _ = file.append(&String::from_iter(&current_line));
+                    let excess = (self.previous_lines.len() + 1).saturating_sub(file.limit);
+                    self.previous_lines.drain(..excess);
+                    self.previous_lines.push(current_line);
+                }
+            }
+            None => self.previous_lines.push(current_line),
+        }
         self.row = self.previous_lines.len();
         self.column = 0;
         true
@@ -145,11 +237,20 @@ impl InputHistory {
     pub fn del_key(&mut self) -> bool {
         self.delete_char()
     }
+
+    /// Replace the characters from column `start` to the cursor with `text`, e.g. a completion
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        self._prepare_modifying_access();
+        let start = start.min(self.column);
+        self.writing_buffer.splice(start..self.column, text.chars());
+        self.column = start + text.chars().count();
+    }
 }
 
 #[cfg(test)]
 mod test_inputhistory {
     use super::*;
+    use crate::testdir::TestDir;
 
     #[test]
     fn test_backspace() {
@@ -187,6 +288,70 @@ mod test_inputhistory {
         assert_eq!(history_test.row(), history_compare.row());
     }
 
+    #[test]
+    fn test_history_file() {
+        let dir = TestDir::new();
+        let path = dir.join("history");
+        fs::write(&path, "bpm 90\nstart\nstop\n").unwrap();
+        let mut history = InputHistory::new();
+        history.load_file(path.clone(), 2).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "start\nstop\n");
+        assert!(history.up());
+        assert_eq!(history.get_line(), "stop");
+        assert!(history.down());
+
+        for line in ["stop", "bpm 120", "", "bpm 120", " secret", "start"] {
+            line.chars().for_each(|c| history.add_char(&c));
+            history.add_line();
+        }
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "start\nstop\nbpm 120\nstart\n"
+        );
+        assert_eq!(history.previous_lines.len(), 2);
+        assert!(history.up());
+        assert_eq!(history.get_line(), "start");
+        assert!(history.up());
+        assert_eq!(history.get_line(), "bpm 120");
+
+        let mut reloaded = InputHistory::new();
+        reloaded.load_file(path.clone(), 2).unwrap();
+        assert_eq!(reloaded.previous_lines, history.previous_lines);
+
+        // a line that another instance appends while the file is loaded is kept
+        let file = HistoryFile {
+            path: path.clone(),
+            limit: 2,
+        };
+        let lock = file.lock().unwrap();
+        let loader = {
+            let path = path.clone();
+            std::thread::spawn(move || InputHistory::new().load_file(path, 2))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let mut other = OpenOptions::new().append(true).open(&path).unwrap();
+        other.write_all(b"bpm 60\n").unwrap();
+        drop(lock);
+        loader.join().unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "start\nbpm 60\n");
+    }
+
+    #[test]
+    fn test_add_line_without_file() {
+        // without a history file every line is recorded
+        let mut history = InputHistory::new();
+        for line in ["stop", "stop", " secret", ""] {
+            line.chars().for_each(|c| history.add_char(&c));
+            history.add_line();
+        }
+        let lines: Vec<String> = history
+            .previous_lines
+            .iter()
+            .map(String::from_iter)
+            .collect();
+        assert_eq!(lines, ["stop", "stop", " secret", ""]);
+    }
+
     #[test]
     fn test_add_line() {
         let mut history_test = InputHistory::new();
@@ -1,2 +1,3 @@
 mod inputhistory;
+#[allow(clippy::module_inception)]
 pub mod repl;
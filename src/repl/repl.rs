@@ -11,12 +11,14 @@ use std::{
     collections::HashMap,
     error::Error,
     fmt,
-    io::{self, Stdout, Write},
+    io::{self, BufRead, Stdout, Write},
+    path::PathBuf,
     result::Result,
     string::String,
     sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{Receiver, Sender},
     sync::Mutex,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use super::inputhistory::InputHistory;
@@ -24,6 +26,18 @@ use super::inputhistory::InputHistory;
 /// CommandFunction is the callback that implements the actual behavior of the command
 type CommandFunction<T> = dyn FnMut(Option<String>, &mut T) -> Result<String, String>;
 
+/// KeyFunction is the callback of a key mode that is called for every pressed character
+type KeyFunction<T> = dyn FnMut(char, &mut T) -> Result<String, String>;
+
+/// ViewFunction renders the app into lines for a terminal of the given columns and rows
+type ViewFunction<T> = dyn FnMut(&mut T, u16, u16) -> Vec<String>;
+
+/// CompletionFunction returns the candidates for an argument of a command
+///
+/// It gets the arguments before the completed one and the completed word so far. Candidates that
+/// do not start with the word are ignored.
+type CompletionFunction<T> = dyn FnMut(&[&str], &str, &mut T) -> Vec<String>;
+
 /// Definition of a command that the REPL recognizes and executes
 struct CommandDefinition<T> {
     /// Name of command, will be matched with the user input
@@ -32,8 +46,52 @@ struct CommandDefinition<T> {
     pub function: Option<Box<CommandFunction<T>>>,
     /// Help message to be displayed after the `function` returns an Error object
     pub help: Option<String>,
+    /// Candidates for the Tab completion of the arguments
+    pub completion: Option<Box<CompletionFunction<T>>>,
+}
+
+/// Candidates of an ambiguous completion that further Tab presses cycle through
+struct CompletionCycle {
+    /// Column at which the completed word starts
+    start: usize,
+    candidates: Vec<String>,
+    /// Candidate that was inserted last
+    index: Option<usize>,
+}
+
+/// Mode in which single key presses are passed to a callback instead of the input line
+///
+/// The mode is entered with the command of the same name and left with ESC or ENTER.
+struct KeyModeDefinition<T> {
+    /// Prompt that is shown while the mode is active
+    pub prompt: String,
+    /// Take a pressed character, do stuff and return a message to display after the prompt
+    pub function: Box<KeyFunction<T>>,
+}
+
+/// Command line of a remote control with the channel for its result
+pub struct RemoteCommand {
+    pub command_line: String,
+    pub reply: Sender<Result<String, String>>,
+}
+
+/// Channels between the REPL and remote controls
+pub struct RemoteChannel {
+    /// Command lines that the REPL executes like entered ones
+    pub commands: Receiver<RemoteCommand>,
+    /// Events of the app
+    pub events: Sender<String>,
 }
 
+/// Interval in which a full-screen view is rendered
+const VIEW_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Longest time in which the REPL does not look for remote commands
+const REMOTE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Interval in which `run_headless` and script waits poll the app
+const HEADLESS_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
 /// REPL built-in commands, may not be overwritten
 const BUILT_INS: [(&str, &str); 3] = [
     ("help", "Display help"),
@@ -61,6 +119,21 @@ impl Error for BuiltInOverwriteError {}
 pub trait ReplApp {
     fn get_status(&mut self) -> String;
     fn get_event_interval(&self) -> Duration;
+
+    /// Lines that describe what happened since the last call, printed by `run_headless`
+    fn poll_events(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether `run_headless` keeps running
+    fn is_active(&mut self) -> bool {
+        true
+    }
+
+    /// Number of completed bars for the script command `wait <n> bars`, `None` if not supported
+    fn bar_count(&mut self) -> Option<u64> {
+        None
+    }
 }
 
 /// Implementation of a Read Print Evaluate Loop (REPL)
@@ -70,9 +143,22 @@ pub trait ReplApp {
 pub struct Repl<T: ReplApp> {
     app: Mutex<T>,
     commands: HashMap<String, CommandDefinition<T>>,
+    key_modes: HashMap<String, KeyModeDefinition<T>>,
+    /// Name of the active key mode and the last message of its callback
+    key_mode: Option<(String, String)>,
+    /// Full-screen views that replace prompt and status line
+    views: HashMap<String, Box<ViewFunction<T>>>,
+    /// Name of the view that is entered or shown
+    view: Option<String>,
+    /// Command lines that are executed when a key is pressed
+    key_bindings: HashMap<KeyCode, String>,
+    /// Remote controls that send command lines and receive events
+    remotes: Vec<RemoteChannel>,
     exit: AtomicBool,
     prompt: String,
     history: InputHistory,
+    /// Ambiguous completion of the last Tab press
+    completion: Option<CompletionCycle>,
 }
 
 impl<T> Repl<T>
@@ -83,9 +169,16 @@ where
         let mut repl = Repl {
             app: Mutex::new(app),
             commands: HashMap::new(),
+            key_modes: HashMap::new(),
+            key_mode: None,
+            views: HashMap::new(),
+            view: None,
+            key_bindings: HashMap::new(),
+            remotes: Vec::new(),
             exit: false.into(),
             prompt,
             history: InputHistory::new(),
+            completion: None,
         };
         for (cmd, help) in BUILT_INS {
             repl.commands.insert(
@@ -94,6 +187,7 @@ where
                     name: cmd.to_string(),
                     function: None,
                     help: Some(help.to_string()),
+                    completion: None,
                 },
             );
         }
@@ -122,6 +216,7 @@ where
             name,
             function: Some(function),
             help,
+            completion: None,
         };
         // make sure that each help command ends with a new line
         if let Some(help_msg) = cmd.help {
@@ -141,6 +236,100 @@ where
         Ok(())
     }
 
+    /// Add or update a key mode
+    ///
+    /// The command `name` enters the key mode. While it is active, every pressed character is
+    /// passed to `function` and its message is displayed after `prompt`.
+    pub fn set_key_mode(
+        &mut self,
+        name: String,
+        prompt: String,
+        function: Box<KeyFunction<T>>,
+        help: Option<String>,
+    ) -> Result<(), BuiltInOverwriteError> {
+        self.set_command(
+            name.clone(),
+            Box::new(|_, _| Err("Key mode can only be entered interactively".to_string())),
+            help,
+        )?;
+        self.key_modes
+            .insert(name, KeyModeDefinition { prompt, function });
+        Ok(())
+    }
+
+    /// Add or update a full-screen view
+    ///
+    /// The command `name` shows the view in the alternate screen until ESC, ENTER or q is pressed.
+    /// `function` renders it at 60 frames per second, SPACE executes the empty command line like
+    /// ENTER at the prompt and key bindings are executed as well.
+    pub fn set_view(
+        &mut self,
+        name: String,
+        function: Box<ViewFunction<T>>,
+        help: Option<String>,
+    ) -> Result<(), BuiltInOverwriteError> {
+        self.set_command(
+            name.clone(),
+            Box::new(|_, _| Err("View can only be shown interactively".to_string())),
+            help,
+        )?;
+        self.views.insert(name, function);
+        Ok(())
+    }
+
+    /// Complete the arguments of the command `name` with the candidates of `function`
+    ///
+    /// Command names and the argument of `help` are completed without a function.
+    pub fn set_completion(
+        &mut self,
+        name: &str,
+        function: Box<CompletionFunction<T>>,
+    ) -> Result<(), String> {
+        match self.commands.get_mut(name) {
+            Some(cmddef) => {
+                cmddef.completion = Some(function);
+                Ok(())
+            }
+            None => Err(format!("Completion for unknown command \"{}\"", name)),
+        }
+    }
+
+    /// Execute `command_line` whenever `key` is pressed outside of key modes
+    ///
+    /// Function keys `F1` to `F12`, `PageUp`, `PageDown`, `Home`, `End` and `Insert` can be bound.
+    pub fn bind_key(&mut self, key: &str, command_line: String) -> Result<(), String> {
+        let key_code = match key {
+            "PageUp" => KeyCode::PageUp,
+            "PageDown" => KeyCode::PageDown,
+            "Home" => KeyCode::Home,
+            "End" => KeyCode::End,
+            "Insert" => KeyCode::Insert,
+            _ => match key.strip_prefix('F').map(|x| x.parse::<u8>()) {
+                Some(Ok(number @ 1..=12)) => KeyCode::F(number),
+                _ => return Err(format!("Key \"{}\" can not be bound", key)),
+            },
+        };
+        self.key_bindings.insert(key_code, command_line);
+        Ok(())
+    }
+
+    /// Keep the last `limit` entered lines in the history file at `path` across sessions
+    pub fn load_history(&mut self, path: PathBuf, limit: usize) -> Result<(), String> {
+        self.history.load_file(path, limit)
+    }
+
+    /// Execute a command line as if it was entered, e.g. to apply settings before `run`
+    pub fn execute(&mut self, command_line: &str) -> Result<String, String> {
+        self.parse_and_execute_command(command_line.to_string())
+    }
+
+    /// Accept command lines from remote controls and send them the events of the app
+    ///
+    /// The remote command `status` returns the status line without styles.
+    pub fn add_remote(&mut self, remote: RemoteChannel) {
+        self.remotes.push(remote);
+    }
+
     /// Start the REPL
     ///
     /// Waits for keyboard events to process them
@@ -154,13 +343,20 @@ where
         self.refresh_prompt_status(&mut stdout, None)?;
 
         while !self.exit.load(Ordering::Relaxed) {
-            if crossterm::event::poll(
-                self.app
-                    .get_mut()
-                    .unwrap()
-                    .get_event_interval()
-                    .div_f64(2.0),
-            )? {
+            if let Some(view) = self.view.clone() {
+                self.show_view(&mut stdout, &view)?;
+                continue;
+            }
+            let mut poll_interval = self
+                .app
+                .get_mut()
+                .unwrap()
+                .get_event_interval()
+                .div_f64(2.0);
+            if !self.remotes.is_empty() {
+                poll_interval = poll_interval.min(REMOTE_POLL_INTERVAL);
+            }
+            if crossterm::event::poll(poll_interval)? {
                 if let Event::Key(event) = crossterm::event::read()? {
                     if event.modifiers == KeyModifiers::CONTROL
                         && (event.code == KeyCode::Char('c') || event.code == KeyCode::Char('d'))
@@ -172,6 +368,13 @@ where
                     }
                 }
             }
+            if !self.remotes.is_empty() {
+                self.dispatch_events(false)?;
+                for msg in self.execute_remote_commands() {
+                    stdout.queue(terminal::ScrollUp(1))?;
+                    self.refresh_prompt_status(&mut stdout, Some(msg))?;
+                }
+            }
             // refresh status prompt for updates
             self.refresh_prompt_status(&mut stdout, None)?;
         }
@@ -184,8 +387,245 @@ where
         Ok(())
     }
 
+    /// Run without terminal interaction until `stop` is set or the app is not active anymore
+    ///
+    /// Neither the raw mode nor the status line is used. Events of the app are printed to stdout
+    /// if `print_events` is set.
+    pub fn run_headless(&mut self, stop: &AtomicBool, print_events: bool) -> io::Result<()> {
+        self.poll_until(stop, print_events, |app| !app.is_active())
+    }
+
+    /// Execute the command lines of a script without terminal interaction
+    ///
+    /// Empty lines and lines starting with `#` are skipped. `wait <seconds>` and `wait <n> bars`
+    /// pause the script while the app keeps running. The script ends at the first error, which
+    /// is returned with its line number, when `stop` is set or with `quit` or `exit`.
+    pub fn run_script<R: BufRead>(
+        &mut self,
+        script: R,
+        stop: &AtomicBool,
+        print_events: bool,
+    ) -> Result<(), String> {
+        self.exit.store(false, Ordering::Relaxed);
+        for (index, line) in script.lines().enumerate() {
+            let error = |err: String| format!("Line {}: {}", index + 1, err.replace("\n\r", "\n"));
+            let line = line.map_err(|err| error(err.to_string()))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if stop.load(Ordering::Relaxed) || self.exit.load(Ordering::Relaxed) {
+                break;
+            }
+            let (command, args) = parse_cmd_w_args(line.to_string());
+            if command == "wait" {
+                self.wait(&args, stop, print_events).map_err(error)?;
+                continue;
+            }
+            let msg = self
+                .parse_and_execute_command(line.to_string())
+                .map_err(error)?;
+            if self.key_mode.take().is_some() {
+                return Err(error("Key modes can not be used in scripts".to_string()));
+            }
+            if self.view.take().is_some() {
+                return Err(error("Views can not be used in scripts".to_string()));
+            }
+            println!("{}", msg.replace("\n\r", "\n"));
+        }
+        Ok(())
+    }
+
+    /// Script command `wait <seconds>` or `wait <n> bars`
+    fn wait(&mut self, args: &str, stop: &AtomicBool, print_events: bool) -> Result<(), String> {
+        let mut words = args.split_whitespace();
+        let amount = words
+            .next()
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x >= 0.0);
+        let result = match (amount, words.next(), words.next()) {
+            (Some(seconds), None, _) => {
+                let end = Instant::now() + Duration::from_secs_f64(seconds);
+                self.poll_until(stop, print_events, |_| Instant::now() >= end)
+            }
+            (Some(bars), Some("bar" | "bars"), None) if bars.fract() == 0.0 => {
+                let start = match self.app.get_mut().unwrap().bar_count() {
+                    Some(start) => start,
+                    None => return Err("Bars are not counted".to_string()),
+                };
+                // stops waiting when the app stops counting bars
+                self.poll_until(stop, print_events, |app| {
+                    !app.is_active() || app.bar_count().is_none_or(|x| x >= start + bars as u64)
+                })
+            }
+            _ => return Err("Expected \"wait <seconds>\" or \"wait <n> bars\"".to_string()),
+        };
+        result.map_err(|err| err.to_string())
+    }
+
+    /// Poll the events of the app and remote commands until `done` or `stop` is set
+    fn poll_until(
+        &mut self,
+        stop: &AtomicBool,
+        print_events: bool,
+        mut done: impl FnMut(&mut T) -> bool,
+    ) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) && !done(self.app.get_mut().unwrap()) {
+            self.dispatch_events(print_events)?;
+            for msg in self.execute_remote_commands() {
+                println!("{}", msg);
+            }
+            std::thread::sleep(HEADLESS_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Pass the events of the app to the remote controls and optionally print them
+    fn dispatch_events(&mut self, print_events: bool) -> io::Result<()> {
+        let events = self.app.get_mut().unwrap().poll_events();
+        if print_events && !events.is_empty() {
+            let mut stdout = io::stdout();
+            for event in &events {
+                writeln!(stdout, "{}", event)?;
+            }
+            stdout.flush()?;
+        }
+        for remote in &self.remotes {
+            for event in &events {
+                // the remote controls may be gone, which does not matter
+                let _ = remote.events.send(event.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute pending remote commands and return messages about them for display
+    fn execute_remote_commands(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        let commands: Vec<RemoteCommand> = self
+            .remotes
+            .iter()
+            .flat_map(|remote| remote.commands.try_iter())
+            .collect();
+        for command in commands {
+            let (parsed_cmd, _) = parse_cmd_w_args(command.command_line.clone());
+            let result = if parsed_cmd == "status" {
+                Ok(strip_styles(&self.app.get_mut().unwrap().get_status()))
+            } else if self.key_modes.contains_key(&parsed_cmd) {
+                Err("Key mode can only be entered interactively".to_string())
+            } else if self.views.contains_key(&parsed_cmd) {
+                Err("View can only be shown interactively".to_string())
+            } else {
+                self.parse_and_execute_command(command.command_line.clone())
+            };
+            if parsed_cmd != "status" {
+                messages.push(match &result {
+                    Ok(msg) => format!("Remote \"{}\": {}", command.command_line, msg),
+                    Err(msg) => format!("Remote \"{}\": Error: {}", command.command_line, msg),
+                });
+            }
+            // the remote control may have disconnected in the meantime
+            let _ = command.reply.send(result);
+        }
+        messages
+    }
+
+    /// Show the full-screen view `name` until it is left
+    ///
+    /// The view is rendered every `VIEW_FRAME_INTERVAL`, independently of the event interval of
+    /// the app. Messages of executed commands are not shown.
+    fn show_view(&mut self, stdout: &mut Stdout, name: &str) -> io::Result<()> {
+        stdout
+            .queue(terminal::EnterAlternateScreen)?
+            .queue(cursor::Hide)?;
+        while !self.exit.load(Ordering::Relaxed) {
+            if crossterm::event::poll(VIEW_FRAME_INTERVAL)? {
+                if let Event::Key(event) = crossterm::event::read()? {
+                    if event.modifiers == KeyModifiers::CONTROL
+                        && (event.code == KeyCode::Char('c') || event.code == KeyCode::Char('d'))
+                    {
+                        self.exit.store(true, Ordering::Relaxed);
+                    } else if event.kind != KeyEventKind::Release {
+                        let command_line = match event.code {
+                            KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => break,
+                            KeyCode::Char(' ') => Some(String::new()),
+                            key => self.key_bindings.get(&key).cloned(),
+                        };
+                        if let Some(command_line) = command_line {
+                            let _ = self.parse_and_execute_command(command_line);
+                        }
+                    }
+                }
+            }
+            if !self.remotes.is_empty() {
+                self.dispatch_events(false)?;
+                self.execute_remote_commands();
+            }
+            let (columns, rows) = terminal::size()?;
+            let view = self.views.get_mut(name).unwrap();
+            let lines = view(self.app.get_mut().unwrap(), columns, rows);
+            for (row, line) in lines.iter().take(rows as usize).enumerate() {
+                stdout
+                    .queue(cursor::MoveTo(0, row as u16))?
+                    .queue(style::Print(line))?
+                    .queue(terminal::Clear(ClearType::UntilNewLine))?;
+            }
+            stdout
+                .queue(cursor::MoveTo(0, lines.len().min(rows as usize) as u16))?
+                .queue(terminal::Clear(ClearType::FromCursorDown))?
+                .flush()?;
+        }
+        self.view = None;
+        stdout
+            .queue(cursor::Show)?
+            .queue(terminal::LeaveAlternateScreen)?
+            .queue(terminal::ScrollUp(1))?;
+        self.refresh_prompt_status(stdout, Some(format!("Leaving {} view", name)))
+    }
+
     /// React on key presses
     fn on_key_pressed(&mut self, stdout: &mut Stdout, key: &KeyCode) -> io::Result<()> {
+        if let Some((mode, mode_message)) = self.key_mode.as_mut() {
+            match key {
+                KeyCode::Esc | KeyCode::Enter => {
+                    let output_msg = format!("Leaving {} mode", mode);
+                    self.key_mode = None;
+                    stdout.queue(terminal::ScrollUp(1))?;
+                    return self.refresh_prompt_status(stdout, Some(output_msg));
+                }
+                KeyCode::Char(c) => {
+                    let key_mode = self.key_modes.get_mut(mode.as_str()).unwrap();
+                    *mode_message = match (key_mode.function)(*c, self.app.get_mut().unwrap()) {
+                        Ok(msg) => msg,
+                        Err(msg) => format!("Error: {}", msg),
+                    };
+                }
+                _ => (),
+            }
+            return self.refresh_prompt_status(stdout, None);
+        }
+
+        if let Some(command_line) = self.key_bindings.get(key).cloned() {
+            let output_msg = match self.parse_and_execute_command(command_line) {
+                Ok(msg) => msg,
+                Err(msg) => format!("Error: {}", msg),
+            };
+            stdout.queue(terminal::ScrollUp(1))?;
+            return self.refresh_prompt_status(stdout, Some(output_msg));
+        }
+
+        if *key == KeyCode::Tab {
+            // ambiguous candidates are listed above the prompt
+            return match self.complete() {
+                Some(listing) => {
+                    stdout.queue(terminal::ScrollUp(1))?;
+                    self.refresh_prompt_status(stdout, Some(listing))
+                }
+                None => self.refresh_prompt_status(stdout, None),
+            };
+        }
+        self.completion = None;
+
         let mut key_message: Option<String> = None;
         let key_press_successful = match key {
             KeyCode::Char(c) => {
@@ -238,7 +678,18 @@ where
     ) -> io::Result<()> {
         let (_, rows) = terminal::size()?;
 
-        let prompt = &self.prompt;
+        let (prompt, line, column) = match &self.key_mode {
+            Some((mode, mode_message)) => {
+                let prompt = &self.key_modes[mode].prompt;
+                let column = prompt.chars().count() + mode_message.chars().count();
+                (prompt, mode_message.clone(), column)
+            }
+            None => (
+                &self.prompt,
+                self.history.get_line(),
+                self.prompt.chars().count() + self.history.column(),
+            ),
+        };
 
         // display output message
         if let Some(msg) = output_msg {
@@ -263,10 +714,8 @@ where
             .queue(terminal::Clear(ClearType::CurrentLine))?
             .queue(cursor::MoveToColumn(0))?
             .queue(style::Print(prompt))?
-            .queue(style::Print(self.history.get_line()))?
-            .queue(cursor::MoveToColumn(
-                (prompt.chars().count() + self.history.column()) as u16,
-            ))?;
+            .queue(style::Print(line))?
+            .queue(cursor::MoveToColumn(column as u16))?;
 
         // make output happen
         stdout.flush()?;
@@ -311,14 +760,31 @@ where
             _ => (),
         }
 
+        // show views
+        if self.views.contains_key(parsed_cmd.as_str()) {
+            let msg = format!(
+                "Showing {} view, press ESC, ENTER or q to leave",
+                parsed_cmd
+            );
+            self.view = Some(parsed_cmd);
+            return Ok(msg);
+        }
+
+        // enter key modes
+        if self.key_modes.contains_key(parsed_cmd.as_str()) {
+            let msg = format!("Entering {} mode, press ESC or ENTER to leave", parsed_cmd);
+            self.key_mode = Some((parsed_cmd, String::new()));
+            return Ok(msg);
+        }
+
         // match custom commands
         match self.commands.get_mut(parsed_cmd.as_str()) {
             Some(cmddef) => {
-                let cmd_result = if cmddef.function.is_some() {
+                let cmd_result = if let Some(function) = cmddef.function.as_mut() {
                     if !args.is_empty() {
-                        (cmddef.function.as_mut().unwrap())(Some(args), self.app.get_mut().unwrap())
+                        function(Some(args), self.app.get_mut().unwrap())
                     } else {
-                        (cmddef.function.as_mut().unwrap())(None, self.app.get_mut().unwrap())
+                        function(None, self.app.get_mut().unwrap())
                     }
                 } else {
                     Err("No function associated".to_string())
@@ -345,6 +811,85 @@ where
         }
     }
 
+    /// Complete the word before the cursor and return the listing of ambiguous candidates
+    ///
+    /// A single candidate is inserted, ambiguous ones are completed to their common prefix and
+    /// further Tab presses cycle through them.
+    fn complete(&mut self) -> Option<String> {
+        if let Some(cycle) = self.completion.as_mut() {
+            let index = cycle.index.map_or(0, |x| (x + 1) % cycle.candidates.len());
+            cycle.index = Some(index);
+            self.history
+                .replace_before_cursor(cycle.start, &cycle.candidates[index]);
+            return None;
+        }
+        let line: String = self
+            .history
+            .get_line()
+            .chars()
+            .take(self.history.column())
+            .collect();
+        let (start, candidates) = self.completion_candidates(&line);
+        match candidates.len() {
+            0 => None,
+            1 => {
+                // directories are completed further
+                let mut candidate = candidates[0].clone();
+                if !candidate.ends_with('/') {
+                    candidate.push(' ');
+                }
+                self.history.replace_before_cursor(start, &candidate);
+                None
+            }
+            _ => {
+                let prefix = candidates[1..]
+                    .iter()
+                    .fold(candidates[0].as_str(), |prefix, x| {
+                        let length: usize = prefix
+                            .chars()
+                            .zip(x.chars())
+                            .take_while(|(a, b)| a == b)
+                            .map(|(a, _)| a.len_utf8())
+                            .sum();
+                        &prefix[..length]
+                    });
+                self.history.replace_before_cursor(start, prefix);
+                let listing = candidates.join("  ");
+                self.completion = Some(CompletionCycle {
+                    start,
+                    candidates,
+                    index: None,
+                });
+                Some(listing)
+            }
+        }
+    }
+
+    /// Sorted candidates for the last word of `line` and the column at which the word starts
+    fn completion_candidates(&mut self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(char::is_whitespace).map_or(0, |x| x + 1);
+        let word = &line[start..];
+        let mut words: Vec<&str> = line[..start].split_whitespace().collect();
+        let mut candidates: Vec<String> = match words.first().copied() {
+            None => self.commands.keys().cloned().collect(),
+            Some("help") if words.len() == 1 => self.commands.keys().cloned().collect(),
+            Some(command) => match self.commands.get_mut(command) {
+                Some(CommandDefinition {
+                    completion: Some(function),
+                    ..
+                }) => {
+                    words.remove(0);
+                    function(&words, word, self.app.get_mut().unwrap())
+                }
+                _ => Vec::new(),
+            },
+        };
+        candidates.retain(|x| !x.is_empty() && x.starts_with(word));
+        candidates.sort();
+        candidates.dedup();
+        (line[..start].chars().count(), candidates)
+    }
+
     fn list_commands(&self) -> String {
         let mut commands = String::new();
         for (cmd, cmddef) in self.commands.iter() {
@@ -361,6 +906,55 @@ where
     }
 }
 
+/// Files and directories that start with `word`, directories end with `/`
+///
+/// Hidden entries are only candidates if the file name in `word` starts with a dot.
+pub fn complete_path(word: &str) -> Vec<String> {
+    let (directory, name) = match word.rfind('/') {
+        Some(index) => (&word[..=index], &word[index + 1..]),
+        None => ("", word),
+    };
+    let entries = match std::fs::read_dir(if directory.is_empty() { "." } else { directory }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let suffix = match entry.path().is_dir() {
+                true => "/",
+                false => "",
+            };
+            Some(format!("{}{}{}", directory, file_name, suffix))
+        })
+        .collect()
+}
+
+/// Remove the ANSI escape sequences of styles from a string
+fn strip_styles(styled: &str) -> String {
+    let mut plain = String::with_capacity(styled.len());
+    let mut chars = styled.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip until the final byte of the control sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
 /// Parse command and arguments from input
 ///
 /// Splits the input string into the first word (command) and the rest of the string (arguments)
@@ -372,10 +966,111 @@ fn parse_cmd_w_args(input: String) -> (String, String) {
         match trimmed_input.find(char::is_whitespace) {
             Some(pos) => (
                 String::from(&trimmed_input[0..pos]),
-                String::from(trimmed_input[pos + 1..].trim_start()),
+                String::from(trimmed_input[pos + 1..].trim()),
             ),
             None => (String::from(trimmed_input), String::from("")),
         }
     };
     (command_str, args_str)
 }
+
+#[cfg(test)]
+mod test_repl {
+    use super::*;
+
+    struct Counter {
+        value: i32,
+    }
+
+    impl ReplApp for Counter {
+        fn get_status(&mut self) -> String {
+            self.value.to_string()
+        }
+
+        fn get_event_interval(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+    }
+
+    #[test]
+    fn test_run_script() {
+        let mut repl = Repl::new(Counter { value: 0 }, String::new());
+        repl.set_command(
+            "add".to_string(),
+            Box::new(|args, counter: &mut Counter| {
+                let value = args.unwrap_or_default().parse::<i32>();
+                counter.value += value.map_err(|err| err.to_string())?;
+                Ok(counter.value.to_string())
+            }),
+            None,
+        )
+        .unwrap();
+        let stop = AtomicBool::new(false);
+
+        let script = "# comment\nadd 2\n\n  add 3\nwait 0.01\n";
+        assert_eq!(repl.run_script(script.as_bytes(), &stop, false), Ok(()));
+        assert_eq!(repl.execute("add 0"), Ok("5".to_string()));
+
+        let script = "add 1\nadd x\nadd 1\n";
+        let err = repl
+            .run_script(script.as_bytes(), &stop, false)
+            .unwrap_err();
+        assert!(err.starts_with("Line 2: Error in command \"add\""));
+        assert_eq!(repl.execute("add 0"), Ok("6".to_string()));
+
+        let script = "wait 2 bars\n";
+        assert_eq!(
+            repl.run_script(script.as_bytes(), &stop, false),
+            Err("Line 1: Bars are not counted".to_string())
+        );
+        let script = "quit\nadd 1\n";
+        assert_eq!(repl.run_script(script.as_bytes(), &stop, false), Ok(()));
+        assert_eq!(repl.execute("add 0"), Ok("6".to_string()));
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut repl = Repl::new(Counter { value: 0 }, String::new());
+        repl.set_command("add".to_string(), Box::new(|_, _| Ok(String::new())), None)
+            .unwrap();
+        repl.set_completion(
+            "add",
+            Box::new(|words, _, counter: &mut Counter| match words.len() {
+                0 => vec!["one".to_string(), "once".to_string(), "two".to_string()],
+                _ => vec![counter.value.to_string()],
+            }),
+        )
+        .unwrap();
+        assert!(repl
+            .set_completion("sub", Box::new(|_, _, _| vec![]))
+            .is_err());
+        let type_text = |repl: &mut Repl<Counter>, text: &str| {
+            text.chars().for_each(|c| repl.history.add_char(&c));
+            repl.completion = None;
+        };
+
+        type_text(&mut repl, "a");
+        assert_eq!(repl.complete(), None);
+        assert_eq!(repl.history.get_line(), "add ");
+        type_text(&mut repl, "o");
+        assert_eq!(repl.complete(), Some("once  one".to_string()));
+        assert_eq!(repl.history.get_line(), "add on");
+        // further Tab presses cycle through the candidates
+        assert_eq!(repl.complete(), None);
+        assert_eq!(repl.history.get_line(), "add once");
+        repl.complete();
+        repl.complete();
+        assert_eq!(repl.history.get_line(), "add once");
+        type_text(&mut repl, " ");
+        repl.complete();
+        assert_eq!(repl.history.get_line(), "add once 0 ");
+
+        let mut repl = Repl::new(Counter { value: 0 }, String::new());
+        type_text(&mut repl, "help e");
+        repl.complete();
+        assert_eq!(repl.history.get_line(), "help exit ");
+        type_text(&mut repl, "q");
+        assert_eq!(repl.complete(), None);
+        assert_eq!(repl.history.get_line(), "help exit q");
+    }
+}
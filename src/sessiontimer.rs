@@ -0,0 +1,167 @@
+use std::{convert::TryFrom, fmt::Display, time::Duration};
+
+use crate::playbackclock::format_elapsed;
+
+/// Limit of a practice session, either playing time or a number of bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerLimit {
+    Time(Duration),
+    Bars(u64),
+}
+
+impl Display for TimerLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimerLimit::Time(time) => write!(f, "{}", format_elapsed(*time)),
+            TimerLimit::Bars(1) => write!(f, "1 bar"),
+            TimerLimit::Bars(bars) => write!(f, "{} bars", bars),
+        }
+    }
+}
+
+/// Practice session timer that stops the playback automatically
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTimer {
+    pub limit: TimerLimit,
+    /// Fade-out time before the end of the session
+    pub fade: Duration,
+    /// Play a chime after the session has ended
+    pub chime: bool,
+}
+
+/// Parse `<duration|<n> bars> [fade <duration>] [chime]`
+impl TryFrom<&str> for SessionTimer {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut words = value.split_whitespace().peekable();
+        let limit = match words.next() {
+            Some(word) => {
+                let bars = match word.strip_suffix("bars").or(word.strip_suffix("bar")) {
+                    Some(bars) => Some(bars),
+                    None if matches!(words.peek(), Some(&"bars") | Some(&"bar")) => {
+                        words.next();
+                        Some(word)
+                    }
+                    None => None,
+                };
+                match bars {
+                    Some(bars) => match bars.parse::<u64>() {
+                        Ok(bars) if bars > 0 => TimerLimit::Bars(bars),
+                        _ => return Err(format!("\"{}\" is not a number of bars", word)),
+                    },
+                    None => TimerLimit::Time(parse_duration(word)?),
+                }
+            }
+            None => return Err("No duration given".to_string()),
+        };
+
+        let mut timer = SessionTimer {
+            limit,
+            fade: Duration::ZERO,
+            chime: false,
+        };
+        while let Some(word) = words.next() {
+            match word {
+                "fade" => match words.next() {
+                    Some(fade) => timer.fade = parse_duration(fade)?,
+                    None => return Err("No fade-out time given".to_string()),
+                },
+                "chime" => timer.chime = true,
+                x => return Err(format!("Unknown timer option \"{}\"", x)),
+            }
+        }
+        Ok(timer)
+    }
+}
+
+impl Display for SessionTimer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.limit)?;
+        if !self.fade.is_zero() {
+            write!(f, ", fade {}s", self.fade.as_secs_f64())?;
+        }
+        if self.chime {
+            write!(f, ", chime")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a duration like `90`, `45s`, `5m`, `1h30m`, `2:30` or `1:00:00`
+///
+/// Plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let error = || format!("Could not parse \"{}\" to a duration", value);
+    let seconds = if value.contains(':') {
+        let mut seconds = 0.0;
+        for part in value.split(':') {
+            seconds = seconds * 60.0 + part.parse::<f64>().map_err(|_| error())?;
+        }
+        seconds
+    } else if let Ok(seconds) = value.parse::<f64>() {
+        seconds
+    } else {
+        let mut seconds = 0.0;
+        let mut number = String::new();
+        for c in value.chars() {
+            let unit = match c {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => {
+                    number.push(c);
+                    continue;
+                }
+            };
+            seconds += number.parse::<f64>().map_err(|_| error())? * unit;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err(error());
+        }
+        seconds
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| error())
+}
+
+#[cfg(test)]
+mod test_sessiontimer {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2:30"), Ok(Duration::from_secs(150)));
+        assert_eq!(parse_duration("1:00:00"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("0.5"), Ok(Duration::from_millis(500)));
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("10m5").is_err());
+        assert!(parse_duration("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_timer() {
+        assert_eq!(
+            SessionTimer::try_from("5m fade 10s chime"),
+            Ok(SessionTimer {
+                limit: TimerLimit::Time(Duration::from_secs(300)),
+                fade: Duration::from_secs(10),
+                chime: true,
+            })
+        );
+        assert_eq!(
+            SessionTimer::try_from("16 bars").map(|t| t.limit),
+            Ok(TimerLimit::Bars(16))
+        );
+        assert_eq!(
+            SessionTimer::try_from("8bars chime").map(|t| t.limit),
+            Ok(TimerLimit::Bars(8))
+        );
+        assert!(SessionTimer::try_from("0 bars").is_err());
+        assert!(SessionTimer::try_from("5m fade").is_err());
+        assert!(SessionTimer::try_from("5m loud").is_err());
+    }
+}
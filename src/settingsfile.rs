@@ -0,0 +1,167 @@
+use std::{fs, io, path::Path};
+
+/// `key = value` line of a settings file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    /// Line number in the file, 0 for entries that were not read from a file
+    pub line: usize,
+}
+
+impl Entry {
+    pub fn new(key: &str, value: String) -> Entry {
+        Entry {
+            key: key.to_string(),
+            value,
+            line: 0,
+        }
+    }
+
+    /// Error message for an invalid value of this entry
+    pub fn invalid(&self, reason: &str) -> String {
+        let message = format!("Invalid {} \"{}\": {}", self.key, self.value, reason);
+        match self.line {
+            0 => message,
+            line => format!("Line {}: {}", line, message),
+        }
+    }
+}
+
+/// `[name]` section of a settings file and its entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+/// Parse the sections of a settings file, entries before the first header belong to a section
+/// with an empty name
+///
+/// Lines starting with `#` or `;` are comments, values may be enclosed in double quotes.
+pub fn parse_settings(content: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        entries: Vec::new(),
+    }];
+    for (index, line) in content.lines().enumerate() {
+        let line_nr = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            match header.strip_suffix(']') {
+                Some(name) if !name.trim().is_empty() => sections.push(Section {
+                    name: name.trim().to_string(),
+                    entries: Vec::new(),
+                }),
+                _ => return Err(format!("Line {}: invalid section header", line_nr)),
+            }
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                sections.last_mut().unwrap().entries.push(Entry {
+                    key: key.trim().to_string(),
+                    value: unquote(value.trim()).to_string(),
+                    line: line_nr,
+                })
+            }
+            _ => return Err(format!("Line {}: expected \"key = value\"", line_nr)),
+        }
+    }
+    if sections[0].entries.is_empty() {
+        sections.remove(0);
+    }
+    Ok(sections)
+}
+
+/// Values in double quotes keep their surrounding whitespace
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(unquoted) => unquoted,
+        None => value,
+    }
+}
+
+/// Format sections so that `parse_settings` reads them back
+pub fn format_settings(sections: &[Section]) -> String {
+    let mut content = String::new();
+    for section in sections {
+        if !section.name.is_empty() {
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content += &format!("[{}]\n", section.name);
+        }
+        for entry in &section.entries {
+            if entry.value.trim() == entry.value {
+                content += &format!("{} = {}\n", entry.key, entry.value);
+            } else {
+                content += &format!("{} = \"{}\"\n", entry.key, entry.value);
+            }
+        }
+    }
+    content
+}
+
+/// Read and parse a settings file, a missing file has no sections
+pub fn read_settings(path: &Path) -> Result<Vec<Section>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            parse_settings(&content).map_err(|err| format!("{}: {}", path.display(), err))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(format!("Could not read \"{}\": {}", path.display(), err)),
+    }
+}
+
+/// Write a file, creating its directory, and replace an existing file only when it is complete
+pub fn write_file(path: &Path, content: &str) -> Result<(), String> {
+    let error = |err: io::Error| format!("Could not write \"{}\": {}", path.display(), err);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, content).map_err(error)?;
+    fs::rename(&temporary, path).map_err(error)
+}
+
+#[cfg(test)]
+mod test_settingsfile {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let content = "# comment\nbpm = 100\n\n[scales]\npattern = !+++|!++\n; comment\nvalue=8\n";
+        let sections = parse_settings(content).unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].name, "");
+        assert_eq!(sections[0].entries[0].key, "bpm");
+        assert_eq!(sections[0].entries[0].line, 2);
+        assert_eq!(sections[1].name, "scales");
+        assert_eq!(sections[1].entries[0].value, "!+++|!++");
+        assert_eq!(sections[1].entries[1].line, 7);
+
+        let formatted = format_settings(&sections);
+        assert_eq!(
+            formatted,
+            "bpm = 100\n\n[scales]\npattern = !+++|!++\nvalue = 8\n"
+        );
+        assert_eq!(parse_settings(&formatted).unwrap()[1].entries.len(), 2);
+
+        let sections = parse_settings("prompt = \"> \"").unwrap();
+        assert_eq!(sections[0].entries[0].value, "> ");
+        assert_eq!(format_settings(&sections), "prompt = \"> \"\n");
+
+        assert_eq!(
+            parse_settings("[]").unwrap_err(),
+            "Line 1: invalid section header"
+        );
+        assert_eq!(
+            parse_settings("\nbpm 100").unwrap_err(),
+            "Line 2: expected \"key = value\""
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    settingsfile::{format_settings, read_settings, write_file, Entry, Section},
+    xdg::data_dir,
+};
+
+/// File that holds the named snapshots of the player settings
+pub fn snapshot_file() -> Result<PathBuf, String> {
+    Ok(data_dir()?.join("snapshots.ini"))
+}
+
+/// Names must be usable as section headers
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(['[', ']']) || name.starts_with(['#', ';']) {
+        Err(format!("\"{}\" is not a valid snapshot name", name))
+    } else {
+        Ok(())
+    }
+}
+
+/// Store settings as snapshot `name`, an existing snapshot with this name is replaced
+pub fn save_snapshot(path: &Path, name: &str, settings: Vec<Entry>) -> Result<(), String> {
+    check_name(name)?;
+    let mut snapshots = read_settings(path)?;
+    let snapshot = Section {
+        name: name.to_string(),
+        entries: settings,
+    };
+    match snapshots.iter_mut().find(|section| section.name == name) {
+        Some(section) => *section = snapshot,
+        None => snapshots.push(snapshot),
+    }
+    write_file(path, &format_settings(&snapshots))
+}
+
+/// Settings of snapshot `name`
+pub fn load_snapshot(path: &Path, name: &str) -> Result<Vec<Entry>, String> {
+    read_settings(path)?
+        .into_iter()
+        .find(|section| section.name == name)
+        .map(|section| section.entries)
+        .ok_or_else(|| format!("No snapshot \"{}\" saved", name))
+}
+
+/// Names of all saved snapshots
+pub fn list_snapshots(path: &Path) -> Result<Vec<String>, String> {
+    Ok(read_settings(path)?
+        .into_iter()
+        .map(|section| section.name)
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod test_snapshots {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn test_save_load_list() {
+        let dir = TestDir::new();
+        let path = dir.join("snapshots.ini");
+        assert_eq!(list_snapshots(&path).unwrap(), Vec::<String>::new());
+
+        let settings = vec![Entry::new("bpm", "90".to_string())];
+        save_snapshot(&path, "scales", settings).unwrap();
+        save_snapshot(&path, "etude 3", vec![Entry::new("bpm", "60".to_string())]).unwrap();
+        save_snapshot(&path, "scales", vec![Entry::new("bpm", "95".to_string())]).unwrap();
+        assert_eq!(list_snapshots(&path).unwrap(), vec!["scales", "etude 3"]);
+        assert_eq!(load_snapshot(&path, "scales").unwrap()[0].value, "95");
+        assert!(load_snapshot(&path, "missing").is_err());
+        assert!(save_snapshot(&path, "[x]", Vec::new()).is_err());
+    }
+}
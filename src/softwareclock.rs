@@ -0,0 +1,155 @@
+use std::{io::Write, sync::atomic::Ordering, thread, time::Instant};
+
+use crate::{
+    audiooutput::AudioRenderer,
+    beatplayer::BeatPatternType,
+    playbackclock::{BeatGrid, PlaybackClock},
+    stoppablethread::{StoppableThread, POLL_INTERVAL},
+};
+
+/// Sample rate of the software clock
+pub const SOFTWARE_SAMPLE_RATE: f64 = 48000.0;
+
+/// Frames that the software clock renders at once, 10 ms
+const BUFFER_FRAMES: usize = 480;
+
+/// Replaces the audio device in the silent mode
+///
+/// Renders mono buffers in real time like an audio callback and discards them, so the clock of
+/// the renderer advances as if the playback was audible.
+pub struct SoftwareClock {
+    _thread: StoppableThread,
+}
+
+impl SoftwareClock {
+    /// Start rendering at `SOFTWARE_SAMPLE_RATE`, one buffer ahead of the time like an audio device
+    pub fn start<R: AudioRenderer>(mut renderer: R) -> SoftwareClock {
+        let thread = StoppableThread::spawn(move |stop| {
+            let start = Instant::now();
+            let mut buffer = vec![0.0f32; BUFFER_FRAMES];
+            let mut rendered = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let due = (start.elapsed().as_secs_f64() * SOFTWARE_SAMPLE_RATE) as u64;
+                while rendered <= due {
+                    renderer.render(&mut buffer);
+                    rendered += BUFFER_FRAMES as u64;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        SoftwareClock { _thread: thread }
+    }
+}
+
+/// Rings the terminal bell at the onset of every beat that is not a pause
+pub struct TerminalBell {
+    _thread: StoppableThread,
+}
+
+impl TerminalBell {
+    pub fn start(clock: PlaybackClock, grid: BeatGrid, pattern: Vec<BeatPatternType>) -> Self {
+        TerminalBell::start_on(clock, grid, pattern, std::io::stdout())
+    }
+
+    /// Ring the bell on `terminal` instead of stdout
+    fn start_on(
+        clock: PlaybackClock,
+        grid: BeatGrid,
+        pattern: Vec<BeatPatternType>,
+        mut terminal: impl Write + Send + 'static,
+    ) -> Self {
+        let thread = StoppableThread::spawn(move |stop| {
+            let mut last_beat = None;
+            while !stop.load(Ordering::Relaxed) {
+                let beat = grid.beat_at_frame(clock.played_frames());
+                if last_beat != Some(beat) {
+                    if pattern[(beat % pattern.len() as u64) as usize] != BeatPatternType::Pause {
+                        let _ = terminal.write_all(b"\x07").and_then(|_| terminal.flush());
+                    }
+                    last_beat = Some(beat);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        TerminalBell { _thread: thread }
+    }
+}
+
+#[cfg(test)]
+mod test_softwareclock {
+    use std::{
+        sync::{atomic::AtomicU64, Arc, Mutex},
+        time::Duration,
+    };
+
+    use cpal::{FromSample, Sample};
+
+    use super::*;
+
+    /// Counts the rendered frames
+    struct FrameCounter(Arc<AtomicU64>);
+
+    impl AudioRenderer for FrameCounter {
+        fn render<T: Sample + FromSample<f32>>(&mut self, data: &mut [T]) {
+            self.0.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Terminal that keeps the written bytes
+    #[derive(Clone, Default)]
+    struct Terminal(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Terminal {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_software_clock() {
+        let frames = Arc::new(AtomicU64::new(0));
+        let start = Instant::now();
+        let clock = SoftwareClock::start(FrameCounter(frames.clone()));
+        thread::sleep(Duration::from_millis(100));
+        let rendered = frames.load(Ordering::Relaxed);
+        let elapsed = start.elapsed().as_secs_f64();
+        drop(clock);
+        // in real time and at most one buffer ahead
+        assert!(
+            rendered >= (0.1 * SOFTWARE_SAMPLE_RATE) as u64,
+            "{}",
+            rendered
+        );
+        let limit = (elapsed * SOFTWARE_SAMPLE_RATE) as u64 + BUFFER_FRAMES as u64;
+        assert!(rendered <= limit, "{} > {}", rendered, limit);
+    }
+
+    #[test]
+    fn test_terminal_bell() {
+        let clock = PlaybackClock::new();
+        let terminal = Terminal::default();
+        let pattern = vec![
+            BeatPatternType::Accent,
+            BeatPatternType::Pause,
+            BeatPatternType::Beat,
+        ];
+        let bell = TerminalBell::start_on(
+            clock.clone(),
+            BeatGrid::constant(100.0),
+            pattern,
+            terminal.clone(),
+        );
+        // the pauses of the second and fifth beat are silent
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(20));
+            clock.advance(100);
+        }
+        thread::sleep(Duration::from_millis(20));
+        drop(bell);
+        assert_eq!(*terminal.0.lock().unwrap(), b"\x07\x07\x07");
+    }
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crossterm::style::{Attribute, Color, SetForegroundColor};
+
+/// Placeholders of a status line template, `{name}` inserts the value
+pub const PLACEHOLDERS: [&str; 15] = [
+    "pattern",
+    "value",
+    "bpm",
+    "accent_hz",
+    "beat_hz",
+    "accent_note",
+    "note",
+    "bar",
+    "pos",
+    "elapsed",
+    "map",
+    "tuning",
+    "timer",
+    "drone",
+    "device",
+];
+
+/// Style tags of a status line template, `<name>` starts and `</name>` ends the style
+pub const STYLES: [&str; 13] = [
+    "bold",
+    "dim",
+    "italic",
+    "underline",
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "grey",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+/// User-defined status line like `<bold>{bpm}</bold> bpm  {pattern}`
+///
+/// `{{` and `}}` are literal braces. The style tags are translated to escape sequences when the
+/// template is parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusTemplate {
+    source: String,
+    parts: Vec<TemplatePart>,
+}
+
+impl StatusTemplate {
+    pub fn parse(source: &str) -> Result<StatusTemplate, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut styles: Vec<&str> = Vec::new();
+        let mut rest = source;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("{{") {
+                text.push('{');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("}}") {
+                text.push('}');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let (name, after) = after
+                    .split_once('}')
+                    .ok_or_else(|| format!("Unclosed placeholder \"{}\"", rest))?;
+                let placeholder = PLACEHOLDERS.iter().find(|x| **x == name).ok_or_else(|| {
+                    format!(
+                        "Unknown placeholder \"{{{}}}\", known are {}",
+                        name,
+                        PLACEHOLDERS.join(", ")
+                    )
+                })?;
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(TemplatePart::Placeholder(placeholder));
+                rest = after;
+            } else if let Some((closing, name, after)) = style_tag(rest) {
+                if !STYLES.contains(&name) {
+                    return Err(format!(
+                        "Unknown style \"{}\", known are {}",
+                        name,
+                        STYLES.join(", ")
+                    ));
+                }
+                if closing {
+                    match styles.pop() {
+                        Some(open) if open == name => {}
+                        Some(open) => {
+                            return Err(format!("\"</{}>\" does not close \"<{}>\"", name, open))
+                        }
+                        None => return Err(format!("\"</{}>\" closes no style", name)),
+                    }
+                    // styles that are still open apply again after the reset
+                    text += &reset_styles();
+                    styles.iter().for_each(|style| text += &style_code(style));
+                } else {
+                    styles.push(name);
+                    text += &style_code(name);
+                }
+                rest = after;
+            } else {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !styles.is_empty() {
+            text += &reset_styles();
+        }
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(StatusTemplate {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    /// Status line with the placeholders replaced by `values`, missing values are empty
+    pub fn render(&self, values: &HashMap<&str, String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.as_str(),
+                TemplatePart::Placeholder(name) => values.get(name).map_or("", |x| x.as_str()),
+            })
+            .collect()
+    }
+}
+
+impl Display for StatusTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Closing flag, name and the rest after a tag like `<red>` or `</red>` at the start of `text`
+fn style_tag(text: &str) -> Option<(bool, &str, &str)> {
+    let tag = text.strip_prefix('<')?;
+    let (closing, tag) = match tag.strip_prefix('/') {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    };
+    let end = tag.find(|c: char| !c.is_ascii_alphabetic())?;
+    let after = tag[end..].strip_prefix('>')?;
+    match end {
+        0 => None,
+        _ => Some((closing, &tag[..end], after)),
+    }
+}
+
+fn style_code(name: &str) -> String {
+    let color = match name {
+        "bold" => return Attribute::Bold.to_string(),
+        "dim" => return Attribute::Dim.to_string(),
+        "italic" => return Attribute::Italic.to_string(),
+        "underline" => return Attribute::Underlined.to_string(),
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ => Color::Grey,
+    };
+    SetForegroundColor(color).to_string()
+}
+
+/// Ends the styles of the tags without the reverse video of the status line
+fn reset_styles() -> String {
+    format!(
+        "{}{}{}{}",
+        Attribute::NormalIntensity,
+        Attribute::NoItalic,
+        Attribute::NoUnderline,
+        SetForegroundColor(Color::Reset)
+    )
+}
+
+#[cfg(test)]
+mod test_statusline {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() {
+        let values = HashMap::from([("bpm", "120".to_string()), ("bar", "3".to_string())]);
+        let template = StatusTemplate::parse("{bpm} bpm {{bar}} <3 {bar}{pos}").unwrap();
+        assert_eq!(template.render(&values), "120 bpm {bar} <3 3");
+        assert_eq!(template.to_string(), "{bpm} bpm {{bar}} <3 {bar}{pos}");
+
+        let template = StatusTemplate::parse("<bold><red>{bpm}</red> bpm</bold>").unwrap();
+        assert_eq!(
+            template.render(&values),
+            format!(
+                "{}{}120{}{} bpm{}",
+                Attribute::Bold,
+                SetForegroundColor(Color::Red),
+                reset_styles(),
+                Attribute::Bold,
+                reset_styles()
+            )
+        );
+
+        assert!(StatusTemplate::parse("{tempo}")
+            .unwrap_err()
+            .starts_with("Unknown placeholder \"{tempo}\", known are pattern, value, bpm"));
+        assert_eq!(
+            StatusTemplate::parse("bpm: {bpm"),
+            Err("Unclosed placeholder \"{bpm\"".to_string())
+        );
+        assert!(StatusTemplate::parse("<blink>")
+            .unwrap_err()
+            .starts_with("Unknown style \"blink\""));
+        assert_eq!(
+            StatusTemplate::parse("<bold><red>x</bold>"),
+            Err("\"</bold>\" does not close \"<red>\"".to_string())
+        );
+    }
+}
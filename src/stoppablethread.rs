@@ -0,0 +1,47 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Interval in which the background threads look at the time, the clock or their sockets
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Thread that is stopped and joined when it is dropped
+///
+/// The thread gets a flag that is set when it has to return.
+pub struct StoppableThread {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StoppableThread {
+    pub fn spawn(run: impl FnOnce(&AtomicBool) + Send + 'static) -> StoppableThread {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || run(&stop))
+        };
+        StoppableThread {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop the thread and wait until it returned
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for StoppableThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
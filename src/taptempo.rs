@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+/// Maximum number of taps that are taken into account
+const MAX_TAPS: usize = 8;
+/// A pause longer than this starts a new tap sequence
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Intervals that deviate more than this from the median are rejected as outliers
+const OUTLIER_TOLERANCE: f64 = 0.2;
+/// Taps after which a running playback follows the tapped tempo
+const SETTLED_TAPS: usize = 4;
+/// A running playback keeps its tempo while the tapped one is closer than this, in bpm
+const SETTLED_TOLERANCE: f64 = 1.0;
+
+/// Tempo detection from key taps
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    taps: Vec<Instant>,
+}
+
+impl TapTempo {
+    pub fn new() -> TapTempo {
+        TapTempo::default()
+    }
+
+    /// Register a tap at `now` and return the tapped beats per minute if there are enough taps
+    pub fn tap(&mut self, now: Instant) -> Option<f64> {
+        if let Some(&last) = self.taps.last() {
+            if now.duration_since(last) > TAP_TIMEOUT {
+                self.taps.clear();
+            }
+        }
+        self.taps.push(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+
+        let intervals: Vec<f64> = self
+            .taps
+            .windows(2)
+            .map(|taps| taps[1].duration_since(taps[0]).as_secs_f64())
+            .collect();
+        average_interval(&intervals).map(|interval| 60.0 / interval)
+    }
+
+    /// Whether the tapped tempo `bpm` should replace `current_bpm`
+    ///
+    /// Setting the tempo restarts a running playback, so it only follows a settled tap sequence
+    /// that differs noticeably.
+    pub fn should_apply(&self, bpm: f64, current_bpm: f64, playing: bool) -> bool {
+        !playing
+            || (self.taps.len() >= SETTLED_TAPS && (bpm - current_bpm).abs() >= SETTLED_TOLERANCE)
+    }
+}
+
+/// Average of the intervals without the outliers
+fn average_interval(intervals: &[f64]) -> Option<f64> {
+    if intervals.is_empty() {
+        return None;
+    }
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = if sorted.len().is_multiple_of(2) {
+        (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
+    } else {
+        sorted[sorted.len() / 2]
+    };
+
+    let inliers: Vec<f64> = intervals
+        .iter()
+        .copied()
+        .filter(|interval| (interval - median).abs() <= median * OUTLIER_TOLERANCE)
+        .collect();
+    if inliers.is_empty() || median <= 0.0 {
+        return None;
+    }
+    Some(inliers.iter().sum::<f64>() / inliers.len() as f64)
+}
+
+#[cfg(test)]
+mod test_taptempo {
+    use super::*;
+
+    #[test]
+    fn test_average_interval_rejects_outliers() {
+        assert_eq!(average_interval(&[]), None);
+        assert_eq!(average_interval(&[0.5]), Some(0.5));
+        let average = average_interval(&[0.5, 0.52, 0.48, 1.0, 0.5]).unwrap();
+        assert!((average - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tap() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+        assert_eq!(tap_tempo.tap(start), None);
+        let bpm = tap_tempo.tap(start + Duration::from_millis(500)).unwrap();
+        assert!((bpm - 120.0).abs() < 1e-6);
+        let bpm = tap_tempo.tap(start + Duration::from_millis(1000)).unwrap();
+        assert!((bpm - 120.0).abs() < 1e-6);
+
+        // a long pause starts over
+        assert_eq!(tap_tempo.tap(start + Duration::from_secs(5)), None);
+        let bpm = tap_tempo.tap(start + Duration::from_millis(6000)).unwrap();
+        assert!((bpm - 60.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_should_apply() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+        for tap in 0..3 {
+            tap_tempo.tap(start + Duration::from_millis(500) * tap);
+        }
+        assert!(tap_tempo.should_apply(120.0, 100.0, false));
+        assert!(!tap_tempo.should_apply(120.0, 100.0, true));
+        tap_tempo.tap(start + Duration::from_millis(1500));
+        assert!(tap_tempo.should_apply(120.0, 100.0, true));
+        assert!(!tap_tempo.should_apply(120.0, 119.5, true));
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt::Display;
+
+use crate::{
+    beatplayer::{format_bpm, BeatPattern, BeatPatternType},
+    midiclock::PulseGrid,
+    playbackclock::{samples_per_beat, BeatGrid},
+};
+
+/// Upper limit for the number of bars of a tempo map
+pub const MAX_TEMPO_MAP_BARS: u64 = 10_000;
+
+/// Consecutive bars with the same meter and tempo
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoSection {
+    pub bars: u64,
+    /// Beats per bar, the first one is accentuated
+    pub beats: u16,
+    pub beat_value: u16,
+    pub bpm: f64,
+}
+
+impl Display for TempoSection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}/{} at {} bpm",
+            self.bars,
+            if self.bars == 1 { "bar" } else { "bars" },
+            self.beats,
+            self.beat_value,
+            format_bpm(self.bpm)
+        )
+    }
+}
+
+/// Sequence of sections that the playback follows bar by bar, e.g. the tempo map of a song
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    pub name: String,
+    pub sections: Vec<TempoSection>,
+}
+
+impl TempoMap {
+    pub fn bars(&self) -> u64 {
+        self.sections.iter().map(|section| section.bars).sum()
+    }
+
+    /// Pattern with the bars of all sections as cycle
+    pub fn pattern(&self) -> BeatPattern {
+        let mut pattern = Vec::new();
+        let mut bars = Vec::new();
+        for section in &self.sections {
+            let beats = section.beats as usize;
+            for _ in 0..section.bars {
+                pattern.push(BeatPatternType::Accent);
+                pattern.extend(std::iter::repeat_n(BeatPatternType::Beat, beats - 1));
+                bars.push(beats);
+            }
+        }
+        BeatPattern {
+            pattern,
+            bars,
+            index: None,
+        }
+    }
+
+    /// Beat grid of the cycle of sections
+    pub fn grid(&self, sample_rate: f64) -> Result<BeatGrid, String> {
+        let sections: Vec<(u64, f64)> = self
+            .sections
+            .iter()
+            .map(|section| {
+                (
+                    section.bars * section.beats as u64,
+                    samples_per_beat(section.bpm, section.beat_value, sample_rate),
+                )
+            })
+            .collect();
+        BeatGrid::cycle(&sections)
+    }
+
+    /// Grid of the MIDI clock pulses of `beats`, the beat grid of the cycle of sections
+    pub fn pulse_grid(&self, beats: BeatGrid) -> Result<PulseGrid, String> {
+        let sections: Vec<(u64, u16)> = self
+            .sections
+            .iter()
+            .map(|section| (section.bars * section.beats as u64, section.beat_value))
+            .collect();
+        PulseGrid::cycle(beats, &sections)
+    }
+
+    /// Index of the section that contains `bar` of the cycle
+    pub fn section_index(&self, bar: u64) -> usize {
+        let mut bar = bar % self.bars();
+        for (index, section) in self.sections.iter().enumerate() {
+            if bar < section.bars {
+                return index;
+            }
+            bar -= section.bars;
+        }
+        self.sections.len() - 1
+    }
+
+    /// Join neighbouring sections with the same meter and tempo and check the map
+    pub fn normalize(mut self) -> Result<TempoMap, String> {
+        self.sections.retain(|section| section.bars > 0);
+        self.sections.dedup_by(|next, section| {
+            let same = (next.beats, next.beat_value, format_bpm(next.bpm))
+                == (section.beats, section.beat_value, format_bpm(section.bpm));
+            if same {
+                section.bars += next.bars;
+            }
+            same
+        });
+        if self.sections.is_empty() {
+            return Err("The tempo map has no bars".to_string());
+        }
+        if self.bars() > MAX_TEMPO_MAP_BARS {
+            return Err(format!(
+                "The tempo map has more than {} bars",
+                MAX_TEMPO_MAP_BARS
+            ));
+        }
+        if let Some(section) = self.sections.iter().find(|section| {
+            section.beats == 0
+                || section.beat_value == 0
+                || !section.bpm.is_finite()
+                || section.bpm <= 0.0
+        }) {
+            return Err(format!("Invalid section: {}", section));
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test_tempomap {
+    use super::*;
+
+    #[test]
+    fn test_tempo_map() {
+        let section = |bars, beats, bpm| TempoSection {
+            bars,
+            beats,
+            beat_value: 4,
+            bpm,
+        };
+        let map = TempoMap {
+            name: "song".to_string(),
+            sections: vec![
+                section(2, 4, 120.0),
+                section(1, 4, 120.0),
+                section(0, 3, 90.0),
+                section(1, 3, 90.0),
+            ],
+        }
+        .normalize()
+        .unwrap();
+        assert_eq!(
+            map.sections,
+            vec![section(3, 4, 120.0), section(1, 3, 90.0)]
+        );
+        assert_eq!(map.pattern().to_string(), "!+++|!+++|!+++|!++");
+        assert_eq!(map.section_index(2), 0);
+        assert_eq!(map.section_index(3), 1);
+        assert_eq!(map.section_index(4), 0);
+
+        // 12 beats of 24000 frames and 3 beats of 32000 frames at 48kHz
+        let grid = map.grid(48000.0).unwrap();
+        assert_eq!(grid.onset(12), 288000);
+        assert_eq!(grid.onset(15), 384000);
+        let pulses = map.pulse_grid(grid.clone()).unwrap();
+        assert_eq!(pulses.onset(12 * 24), 288000);
+        assert_eq!(pulses.onset(15 * 24), 384000);
+        assert_eq!(pulses.onset(12 * 24 + 1), 289333);
+
+        // a beat of 1/256 has less than one pulse, every third pulse is on the 8th beat
+        let map = TempoMap {
+            name: "fast".to_string(),
+            sections: vec![TempoSection {
+                bars: 1,
+                beats: 1,
+                beat_value: 256,
+                bpm: 120.0,
+            }],
+        };
+        let grid = map.grid(48000.0).unwrap();
+        assert_eq!(grid.onset(8), 3000);
+        let pulses = map.pulse_grid(grid).unwrap();
+        assert_eq!(pulses.onset(1), 1000);
+        assert_eq!(pulses.onset(3), 3000);
+
+        let empty = TempoMap {
+            name: String::new(),
+            sections: vec![section(0, 4, 120.0)],
+        };
+        assert!(empty.normalize().is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Number of the next test directory of this process
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Empty temporary directory of a test that is removed with its content when it is dropped
+///
+/// The name contains the process id and a counter, so parallel tests and test runs do not share
+/// their files.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new() -> TestDir {
+        let number = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("mnomer-{}-{}", std::process::id(), number));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+
+    /// Path of the file `name` in the directory
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
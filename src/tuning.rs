@@ -0,0 +1,422 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::pitch::parse_note;
+
+/// MIDI note number of A4
+const A4_NOTE: i32 = 69;
+/// MIDI note number of C4, the default root of the tunings
+const C4_NOTE: i32 = 60;
+/// Largest map size of a keyboard mapping, one entry per MIDI note
+const MAX_KBM_SIZE: usize = 128;
+
+/// Keyboard mapping of a Scala `.kbm` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    /// Note that is mapped to the first entry of `keys`
+    pub middle_note: i32,
+    /// Note that has `reference_frequency`
+    pub reference_note: i32,
+    pub reference_frequency: f64,
+    /// Scale degree that is the period of the mapping
+    pub octave_degree: usize,
+    /// Scale degree of each key, `None` for unmapped keys, empty for a linear mapping
+    pub keys: Vec<Option<usize>>,
+}
+
+/// Maps notes to frequencies
+///
+/// A tuning consists of the scale degrees within one period, usually an octave, and the note
+/// that is the root of the scale. Note names, intervals and relative pitches resolve through it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub name: String,
+    /// Frequency ratios of the scale degrees relative to the root, starting with 1/1, the period
+    /// is the last ratio
+    pub ratios: Vec<f64>,
+    /// Note of the first scale degree
+    pub root: i32,
+    /// Keyboard mapping from a `.kbm` file, replaces the root and the A4 reference
+    pub mapping: Option<KeyboardMapping>,
+    /// Scala `.scl` and `.kbm` files that the tuning was loaded from
+    pub files: Option<(PathBuf, Option<PathBuf>)>,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::temperament("equal", C4_NOTE).unwrap()
+    }
+}
+
+impl Display for Tuning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mapping {
+            Some(_) => write!(f, "{}", self.name),
+            None => write!(f, "{} ({})", self.name, root_name(self.root)),
+        }
+    }
+}
+
+impl Tuning {
+    /// Names of the built-in temperaments
+    pub const TEMPERAMENTS: [&'static str; 4] = ["equal", "just", "pythagorean", "meantone"];
+
+    /// Built-in 12 tone temperament with the root `root`
+    pub fn temperament(name: &str, root: i32) -> Result<Tuning, String> {
+        let ratios = match name {
+            "equal" => (0..=12).map(|step| 2f64.powf(step as f64 / 12.0)).collect(),
+            // 5-limit just intonation
+            "just" => ratios(&[
+                (1, 1),
+                (16, 15),
+                (9, 8),
+                (6, 5),
+                (5, 4),
+                (4, 3),
+                (45, 32),
+                (3, 2),
+                (8, 5),
+                (5, 3),
+                (9, 5),
+                (15, 8),
+                (2, 1),
+            ]),
+            "pythagorean" => ratios(&[
+                (1, 1),
+                (256, 243),
+                (9, 8),
+                (32, 27),
+                (81, 64),
+                (4, 3),
+                (729, 512),
+                (3, 2),
+                (128, 81),
+                (27, 16),
+                (16, 9),
+                (243, 128),
+                (2, 1),
+            ]),
+            // quarter-comma meantone, chain of pure major thirds divided into four fifths
+            "meantone" => {
+                let fifth = 5f64.powf(0.25);
+                let mut ratios = vec![0.0; 12];
+                for fifths in -3i32..=8 {
+                    let mut ratio = fifth.powi(fifths);
+                    while ratio >= 2.0 {
+                        ratio /= 2.0;
+                    }
+                    while ratio < 1.0 {
+                        ratio *= 2.0;
+                    }
+                    ratios[(fifths * 7).rem_euclid(12) as usize] = ratio;
+                }
+                ratios.push(2.0);
+                ratios
+            }
+            x => {
+                return Err(format!(
+                    "Unknown temperament \"{}\", known are {}",
+                    x,
+                    Tuning::TEMPERAMENTS.join(", ")
+                ))
+            }
+        };
+        Ok(Tuning {
+            name: name.to_string(),
+            ratios,
+            root,
+            mapping: None,
+            files: None,
+        })
+    }
+
+    /// Load a scale from a Scala `.scl` file and optionally its keyboard mapping from a `.kbm` file
+    pub fn load(scl: &Path, kbm: Option<&Path>, root: i32) -> Result<Tuning, String> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map_err(|err| format!("Could not read \"{}\": {}", path.display(), err))
+        };
+        let (name, ratios) = parse_scl(&read(scl)?)?;
+        let mapping = match kbm {
+            Some(kbm) => Some(parse_kbm(&read(kbm)?)?),
+            None => None,
+        };
+        let name = if name.is_empty() {
+            scl.display().to_string()
+        } else {
+            name
+        };
+        Ok(Tuning {
+            name,
+            ratios,
+            root,
+            mapping,
+            files: Some((scl.to_path_buf(), kbm.map(Path::to_path_buf))),
+        })
+    }
+
+    /// Root note with octave like `D4`, as accepted by `parse_root`
+    pub fn root_note(&self) -> String {
+        format!("{}{}", root_name(self.root), self.root.div_euclid(12) - 1)
+    }
+
+    /// Number of scale degrees within one period
+    fn size(&self) -> i64 {
+        self.ratios.len() as i64 - 1
+    }
+
+    /// Frequency ratio of a scale degree relative to the root, degrees may exceed the period
+    fn degree_ratio(&self, degree: i64) -> f64 {
+        let period = self.ratios[self.ratios.len() - 1];
+        let periods = degree.div_euclid(self.size());
+        self.ratios[degree.rem_euclid(self.size()) as usize] * period.powi(periods as i32)
+    }
+
+    /// Scale degree of a note, `None` if the note is not mapped
+    fn note_degree(&self, note: i32) -> Option<i64> {
+        match &self.mapping {
+            Some(mapping) if !mapping.keys.is_empty() => {
+                let keys = mapping.keys.len() as i64;
+                let distance = (note - mapping.middle_note) as i64;
+                let key = mapping.keys[distance.rem_euclid(keys) as usize]?;
+                Some(key as i64 + distance.div_euclid(keys) * mapping.octave_degree as i64)
+            }
+            Some(mapping) => Some((note - mapping.middle_note) as i64),
+            None => Some((note - self.root) as i64),
+        }
+    }
+
+    /// Frequency of a MIDI note number, A4 has the frequency `a4` unless a keyboard mapping
+    /// defines a different reference
+    pub fn frequency(&self, note: i32, a4: f64) -> Result<f64, String> {
+        let (reference_note, reference_frequency) = match &self.mapping {
+            Some(mapping) => (mapping.reference_note, mapping.reference_frequency),
+            None => (A4_NOTE, a4),
+        };
+        let unmapped = |note| format!("Note {} is not mapped in tuning {}", note, self.name);
+        let degree = self.note_degree(note).ok_or_else(|| unmapped(note))?;
+        let reference_degree = self
+            .note_degree(reference_note)
+            .ok_or_else(|| unmapped(reference_note))?;
+        Ok(reference_frequency * self.degree_ratio(degree) / self.degree_ratio(reference_degree))
+    }
+
+    /// Frequency ratio of an interval of `steps` scale degrees
+    ///
+    /// Fractional steps fall back to equal temperament.
+    pub fn steps_ratio(&self, steps: f64) -> f64 {
+        if steps.fract() == 0.0 {
+            self.degree_ratio(steps as i64)
+        } else {
+            2f64.powf(steps / 12.0)
+        }
+    }
+}
+
+fn ratios(fractions: &[(u32, u32)]) -> Vec<f64> {
+    fractions
+        .iter()
+        .map(|&(numerator, denominator)| numerator as f64 / denominator as f64)
+        .collect()
+}
+
+/// Parse a root note like `D`, `Bb` or `F#3`, the octave defaults to 4
+pub fn parse_root(value: &str) -> Option<i32> {
+    parse_note(value).or_else(|| parse_note(&format!("{}4", value)))
+}
+
+fn root_name(root: i32) -> &'static str {
+    [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ][root.rem_euclid(12) as usize]
+}
+
+/// Lines of a Scala file without comments
+fn scala_lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('!'))
+        .map(|(index, line)| (index + 1, line.trim()))
+}
+
+/// Parse the description and the ratios of the scale degrees of a `.scl` file
+fn parse_scl(content: &str) -> Result<(String, Vec<f64>), String> {
+    let mut lines = scala_lines(content);
+    let description = match lines.next() {
+        Some((_, description)) => description.to_string(),
+        None => return Err("Scala file is empty".to_string()),
+    };
+    let count = match lines.next() {
+        Some((line_nr, line)) => first_word(line)
+            .parse::<usize>()
+            .map_err(|_| format!("Line {}: \"{}\" is not a number of notes", line_nr, line))?,
+        None => return Err("Scala file has no number of notes".to_string()),
+    };
+    if count == 0 {
+        return Err("Scala file has no notes".to_string());
+    }
+
+    let mut ratios = vec![1.0];
+    for (line_nr, line) in lines.take(count) {
+        let pitch = first_word(line);
+        let ratio = if pitch.contains('.') {
+            pitch
+                .parse::<f64>()
+                .map(|cents| 2f64.powf(cents / 1200.0))
+                .ok()
+        } else {
+            let mut parts = pitch.splitn(2, '/');
+            let numerator = parts.next().and_then(|x| x.parse::<f64>().ok());
+            let denominator = match parts.next() {
+                Some(x) => x.parse::<f64>().ok(),
+                None => Some(1.0),
+            };
+            match (numerator, denominator) {
+                (Some(numerator), Some(denominator)) if denominator != 0.0 => {
+                    Some(numerator / denominator)
+                }
+                _ => None,
+            }
+        };
+        match ratio {
+            Some(ratio) if ratio > 0.0 => ratios.push(ratio),
+            _ => return Err(format!("Line {}: \"{}\" is not a pitch", line_nr, line)),
+        }
+    }
+    if ratios.len() != count + 1 {
+        return Err(format!(
+            "Scala file has {} instead of {} notes",
+            ratios.len() - 1,
+            count
+        ));
+    }
+    Ok((description, ratios))
+}
+
+/// Parse a keyboard mapping of a `.kbm` file
+fn parse_kbm(content: &str) -> Result<KeyboardMapping, String> {
+    let mut lines = scala_lines(content).filter(|(_, line)| !line.is_empty());
+    let mut next_number = |what: &str| -> Result<f64, String> {
+        match lines.next() {
+            Some((line_nr, line)) => first_word(line)
+                .parse::<f64>()
+                .map_err(|_| format!("Line {}: \"{}\" is not a {}", line_nr, line, what)),
+            None => Err(format!("Keyboard mapping has no {}", what)),
+        }
+    };
+    let size = next_number("map size")?;
+    if !(0.0..=MAX_KBM_SIZE as f64).contains(&size) {
+        return Err(format!(
+            "Keyboard mapping size {} is not within [0; {}]",
+            size, MAX_KBM_SIZE
+        ));
+    }
+    let size = size as usize;
+    // first and last note to retune are not needed
+    next_number("first note")?;
+    next_number("last note")?;
+    let middle_note = next_number("middle note")? as i32;
+    let reference_note = next_number("reference note")? as i32;
+    let reference_frequency = next_number("reference frequency")?;
+    let octave_degree = next_number("octave degree")? as usize;
+
+    let mut keys = Vec::with_capacity(size);
+    for (line_nr, line) in lines.take(size) {
+        keys.push(match first_word(line) {
+            "x" => None,
+            degree => Some(
+                degree
+                    .parse::<usize>()
+                    .map_err(|_| format!("Line {}: \"{}\" is not a scale degree", line_nr, line))?,
+            ),
+        });
+    }
+    // missing entries at the end are unmapped
+    keys.resize(size, None);
+    Ok(KeyboardMapping {
+        middle_note,
+        reference_note,
+        reference_frequency,
+        octave_degree,
+        keys,
+    })
+}
+
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+#[cfg(test)]
+mod test_tuning {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_temperaments() {
+        let equal = Tuning::default();
+        assert_close(equal.frequency(69, 440.0).unwrap(), 440.0);
+        assert_close(equal.frequency(81, 440.0).unwrap(), 880.0);
+        assert_close(equal.frequency(60, 440.0).unwrap(), 261.625565);
+
+        // just major third above the root D
+        let just = Tuning::temperament("just", 62).unwrap();
+        let d = just.frequency(62, 440.0).unwrap();
+        assert_close(just.frequency(66, 440.0).unwrap() / d, 1.25);
+        // A is a pure fifth above D and keeps the reference
+        assert_close(just.frequency(69, 440.0).unwrap(), 440.0);
+        assert_close(d, 440.0 / 1.5);
+
+        let meantone = Tuning::temperament("meantone", 60).unwrap();
+        assert_close(meantone.ratios[4], 1.25);
+        assert_close(meantone.ratios[7], 5f64.powf(0.25));
+        assert_close(meantone.steps_ratio(12.0), 2.0);
+
+        assert!(Tuning::temperament("wolf", 60).is_err());
+    }
+
+    #[test]
+    fn test_parse_scl() {
+        let scl = "! test.scl\n!\nPentatonic\n 5\n!\n 9/8\n 5/4\n 701.955 cents\n 5/3\n 2\n";
+        let (name, ratios) = parse_scl(scl).unwrap();
+        assert_eq!(name, "Pentatonic");
+        assert_eq!(ratios.len(), 6);
+        assert_close(ratios[3], 1.5);
+        assert_close(ratios[5], 2.0);
+
+        assert!(parse_scl("Broken\n 2\n 9/8\n").is_err());
+        assert!(parse_scl("Broken\n 1\n abc\n").is_err());
+    }
+
+    #[test]
+    fn test_kbm_mapping() {
+        let (name, ratios) = parse_scl("Pentatonic\n5\n9/8\n5/4\n3/2\n5/3\n2/1\n").unwrap();
+        let kbm =
+            "! white keys\n12\n0\n127\n60\n69\n440.0\n5\n0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\nx\n";
+        let tuning = Tuning {
+            name,
+            ratios,
+            root: 60,
+            mapping: Some(parse_kbm(kbm).unwrap()),
+            files: None,
+        };
+        assert_close(tuning.frequency(69, 0.0).unwrap(), 440.0);
+        assert_close(tuning.frequency(60, 0.0).unwrap(), 440.0 / (5.0 / 3.0));
+        assert_close(
+            tuning.frequency(72, 0.0).unwrap(),
+            2.0 * 440.0 / (5.0 / 3.0),
+        );
+        assert!(tuning.frequency(61, 0.0).is_err());
+
+        assert_eq!(
+            parse_kbm("1e12\n0\n127\n60\n69\n440\n12\n"),
+            Err("Keyboard mapping size 1000000000000 is not within [0; 128]".to_string())
+        );
+    }
+}
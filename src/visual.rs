@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use crossterm::style::Stylize;
+
+use crate::beatplayer::{format_bpm, BeatPatternType, PlayedBeat};
+
+/// Longest time that the beat indicator lights up after the onset of a beat
+const FLASH_TIME: Duration = Duration::from_millis(150);
+
+/// Rows of the view below the beat indicator
+const INFO_ROWS: usize = 11;
+
+/// Glyphs of the big digits, 5 rows each
+const DIGITS: [[&str; 5]; 10] = [
+    ["███", "█ █", "█ █", "█ █", "███"],
+    [" █ ", "██ ", " █ ", " █ ", "███"],
+    ["███", "  █", "███", "█  ", "███"],
+    ["███", "  █", "███", "  █", "███"],
+    ["█ █", "█ █", "███", "  █", "  █"],
+    ["███", "█  ", "███", "  █", "███"],
+    ["███", "█  ", "███", "█ █", "███"],
+    ["███", "  █", " █ ", " █ ", " █ "],
+    ["███", "█ █", "███", "█ █", "███"],
+    ["███", "█ █", "███", "  █", "███"],
+];
+
+/// Lines of the full-screen visual metronome for a terminal of `columns` x `rows`
+///
+/// A block flashes at the onset of every beat, yellow for accents and cyan for normal beats,
+/// above the beats of the bar, the bpm in big digits and the current bar and beat. `bpm` is shown
+/// while the playback is stopped.
+pub fn visual_lines(beat: Option<&PlayedBeat>, bpm: f64, columns: u16, rows: u16) -> Vec<String> {
+    let (columns, rows) = (columns as usize, rows as usize);
+    let mut lines = Vec::new();
+
+    let flash = beat.and_then(|beat| {
+        let lit = beat.since_onset < FLASH_TIME.min(beat.length / 2);
+        match beat.beat_type {
+            BeatPatternType::Accent if lit => Some(" ".repeat(columns).on_yellow()),
+            BeatPatternType::Beat if lit => Some(" ".repeat(columns).on_cyan()),
+            _ => None,
+        }
+    });
+    for _ in 0..rows.saturating_sub(INFO_ROWS).max(1) {
+        lines.push(match &flash {
+            Some(block) => block.to_string(),
+            None => String::new(),
+        });
+    }
+    lines.push(String::new());
+
+    // the beats of the bar with the current one filled
+    lines.push(match beat {
+        Some(beat) => {
+            let dots: Vec<String> = (1..=beat.beats_in_bar)
+                .map(|index| match index == beat.position.beat {
+                    true if beat.beat_type == BeatPatternType::Accent => "●".yellow().to_string(),
+                    true => "●".cyan().to_string(),
+                    false => "○".to_string(),
+                })
+                .collect();
+            let width = (beat.beats_in_bar * 2).saturating_sub(1) as usize;
+            padding(width, columns) + &dots.join(" ")
+        }
+        None => String::new(),
+    });
+    lines.push(String::new());
+
+    let bpm = format_bpm(beat.map_or(bpm, |beat| beat.bpm));
+    lines.extend((0..5).map(|row| {
+        let glyphs: Vec<&str> = bpm
+            .chars()
+            .map(|c| match c.to_digit(10) {
+                Some(digit) => DIGITS[digit as usize][row],
+                None if row == 4 => "█",
+                None => " ",
+            })
+            .collect();
+        let line = glyphs.join(" ");
+        padding(line.chars().count(), columns) + &line
+    }));
+    lines.push(padding(3, columns) + "bpm");
+    lines.push(String::new());
+
+    let info = match beat {
+        Some(beat) => format!(
+            "bar {}  beat {}/{}",
+            beat.position.bar, beat.position.beat, beat.beats_in_bar
+        ),
+        None => "stopped".to_string(),
+    };
+    lines.push(padding(info.chars().count(), columns) + &info);
+    lines
+}
+
+/// Spaces that center a text of `width` characters
+fn padding(width: usize, columns: usize) -> String {
+    " ".repeat(columns.saturating_sub(width) / 2)
+}
+
+#[cfg(test)]
+mod test_visual {
+    use super::*;
+    use crate::playbackclock::MusicalPosition;
+
+    #[test]
+    fn test_visual_lines() {
+        let mut beat = PlayedBeat {
+            position: MusicalPosition {
+                bar: 12,
+                beat: 1,
+                tick: 0,
+            },
+            beats_in_bar: 4,
+            beat_type: BeatPatternType::Accent,
+            since_onset: Duration::from_millis(20),
+            length: Duration::from_millis(500),
+            bpm: 120.0,
+        };
+        let lines = visual_lines(Some(&beat), 100.0, 20, 24);
+        assert_eq!(lines.len(), 24);
+        assert_eq!(lines[0], " ".repeat(20).on_yellow().to_string());
+        assert_eq!(lines[14], format!("      {} ○ ○ ○", "●".yellow()));
+        assert_eq!(lines[16], "     █  ███ ███");
+        assert_eq!(lines[20], "    ███ ███ ███");
+        assert_eq!(lines[23], "  bar 12  beat 1/4");
+
+        // the flash is over
+        beat.since_onset = Duration::from_millis(200);
+        assert_eq!(visual_lines(Some(&beat), 100.0, 20, 24)[0], "");
+        let stopped = visual_lines(None, 93.5, 20, 5);
+        assert_eq!(stopped.len(), 12);
+        assert_eq!(stopped[8], "   ███ ███ █ ███");
+        assert_eq!(stopped.last().unwrap(), "      stopped");
+    }
+}
@@ -0,0 +1,32 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Directory of mnomer below the XDG base directory `variable`, `fallback` is relative to HOME
+fn app_dir(variable: &str, fallback: &str) -> Result<PathBuf, String> {
+    let base = match env::var_os(variable) {
+        // relative paths are invalid according to the XDG base directory specification
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(fallback),
+            None => return Err(format!("Neither {} nor HOME is set", variable)),
+        },
+    };
+    Ok(base.join("mnomer"))
+}
+
+/// `$XDG_DATA_HOME/mnomer`, defaults to `~/.local/share/mnomer`
+pub fn data_dir() -> Result<PathBuf, String> {
+    app_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// `$XDG_CONFIG_HOME/mnomer`, defaults to `~/.config/mnomer`
+pub fn config_dir() -> Result<PathBuf, String> {
+    app_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME/mnomer`, defaults to `~/.local/state/mnomer`
+pub fn state_dir() -> Result<PathBuf, String> {
+    app_dir("XDG_STATE_HOME", ".local/state")
+}